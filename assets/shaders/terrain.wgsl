@@ -0,0 +1,58 @@
+#import bevy_pbr::{
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::{alpha_discard, apply_pbr_lighting, main_pass_post_lighting_processing},
+    forward_io::{VertexOutput, FragmentOutput},
+}
+
+struct TerrainLayers {
+    colors: array<vec4<f32>, 4>,
+    rock_slope: f32,
+    slope_blend: f32,
+    sand_height: f32,
+    height_blend: f32,
+    auto_texture: u32,
+}
+
+@group(1) @binding(100)
+var<uniform> layers: TerrainLayers;
+
+// Grass on flats, rock on steep faces & sand at low altitude.
+fn auto_weights(world_position: vec3<f32>, world_normal: vec3<f32>) -> vec4<f32> {
+    let slope = 1.0 - normalize(world_normal).y;
+    let rock = smoothstep(layers.rock_slope - layers.slope_blend, layers.rock_slope + layers.slope_blend, slope);
+    let low = 1.0 - smoothstep(layers.sand_height - layers.height_blend, layers.sand_height + layers.height_blend, world_position.y);
+    let sand = (1.0 - rock) * low;
+    return vec4<f32>(0.0, 1.0 - rock - sand, rock, sand);
+}
+
+@fragment
+fn fragment(
+    in: VertexOutput,
+    @builtin(front_facing) is_front: bool,
+) -> FragmentOutput {
+    var pbr_input = pbr_input_from_standard_material(in, is_front);
+
+    // the splat map: layer weights in vertex colors
+#ifdef VERTEX_COLORS
+    var weights = in.color;
+#else
+    var weights = vec4<f32>(1.0, 0.0, 0.0, 0.0);
+#endif
+    if layers.auto_texture != 0u {
+        // the first layer is left to the rule
+        weights = weights.x * auto_weights(in.world_position.xyz, in.world_normal) + vec4<f32>(0.0, weights.yzw);
+    }
+    weights = weights / max(dot(weights, vec4<f32>(1.0)), 0.0001);
+    let color = weights.x * layers.colors[0]
+        + weights.y * layers.colors[1]
+        + weights.z * layers.colors[2]
+        + weights.w * layers.colors[3];
+    pbr_input.material.base_color = vec4<f32>(color.rgb, pbr_input.material.base_color.a);
+
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+
+    var out: FragmentOutput;
+    out.color = apply_pbr_lighting(pbr_input);
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+    return out;
+}
@@ -1,56 +1,534 @@
-use bevy::prelude::*;
+use bevy::{
+    ecs::system::{Command, EntityCommand},
+    math::Affine3A,
+    prelude::*,
+    transform::TransformSystem,
+    utils::{HashMap, HashSet},
+};
+use bevy_xpbd_3d::prelude::*;
+
+use crate::{
+    ai::{building_path::BuildingAgent, building_stairs::ConnectorPart, terrain::Terrain},
+    camera::MainCamera,
+    mesh::hexahedron::Hexahedron,
+    ui::{
+        basic_materials::BasicMaterials,
+        selection::{Layer, Selectable},
+        side_panel::{SidePanel, UiMode},
+    },
+};
 
 pub struct BuildingPlugin;
 
 impl Plugin for BuildingPlugin {
     fn build(&self, app: &mut App) {
         app.register_type::<Buildings>()
-            .init_resource::<Buildings>();
+            .init_resource::<Buildings>()
+            .init_resource::<DoorProbeTimer>()
+            .add_event::<DoorChangedEvent>()
+            .add_systems(
+                Update,
+                (
+                    add_building,
+                    add_door,
+                    anchor_wall,
+                    update_wall_anchors,
+                    probe_doors,
+                    draw_doors,
+                ),
+            )
+            .add_systems(
+                PostUpdate,
+                update_buildings.after(TransformSystem::TransformPropagate),
+            );
     }
 }
 
-#[derive(Resource, Reflect)]
-#[reflect(Resource)]
-pub struct Buildings {}
-
-impl Default for Buildings {
-    fn default() -> Self {
-        Self {}
-    }
-}
+/// Thickness of floor tiles. Their top is at the floor's origin.
+pub const SLAB_THICKNESS: f32 = 0.2;
+/// Height of the foundation above the building's origin. The ground floor sits on top of it.
+pub const FOUNDATION_HEIGHT: f32 = 0.5;
+/// How deep the foundation goes below the building's origin.
+const FOUNDATION_DEPTH: f32 = 0.5;
 
 /// The root of a building structure. Contains some floors.
 ///
 /// Pathfinding in a building is hierarchical. First a generic path is found in the graph of rooms & doors,
 /// then, optionally, explicit paths in each room's nav mesh.
-#[derive(Component)]
-pub struct Building;
+///
+/// Floors are laid out on a grid of square cells in the building's XZ plane.
+/// Cell `(x, y)` spans `x * tile_size..(x + 1) * tile_size` on X and likewise on Z.
+#[derive(Component, Clone)]
+pub struct Building {
+    pub tile_size: f32,
+    /// Distance between the origins of consecutive floors.
+    pub floor_height: f32,
+    pub wall_thickness: f32,
+}
+
+impl Building {
+    /// Height of the walls, from the floor up to the slab of the floor above.
+    pub fn wall_height(&self) -> f32 {
+        self.floor_height - SLAB_THICKNESS
+    }
+
+    /// Origin of a floor, in building space.
+    pub fn floor_origin(&self, level: u32) -> Vec3 {
+        Vec3::Y * (FOUNDATION_HEIGHT + level as f32 * self.floor_height)
+    }
+
+    /// Center of a cell, in floor space.
+    pub fn cell_center(&self, cell: IVec2) -> Vec3 {
+        Vec3::new(
+            (cell.x as f32 + 0.5) * self.tile_size,
+            0.,
+            (cell.y as f32 + 0.5) * self.tile_size,
+        )
+    }
+
+    /// A grid corner, in floor space.
+    pub fn corner(&self, corner: IVec2) -> Vec3 {
+        Vec3::new(
+            corner.x as f32 * self.tile_size,
+            0.,
+            corner.y as f32 * self.tile_size,
+        )
+    }
+
+    /// The cell containing a point in floor (or building) space.
+    pub fn cell_at(&self, point: Vec3) -> IVec2 {
+        IVec2::new(
+            (point.x / self.tile_size).floor() as i32,
+            (point.z / self.tile_size).floor() as i32,
+        )
+    }
+}
 
 /// Top level element of buildings. Contains purely physical elements such as floor tiles & walls,
 /// and navigation elements, such as rooms & doors.
 ///
 /// Can be resized, leading to cascaded resizing of anchored floor tiles & walls, & optionally floor(s) above.
 /// Can be extended by extruding from a selected section, leading to generating new external walls & optionally floor(s) above.
-#[derive(Component)]
-pub struct Floor;
+#[derive(Component, Clone)]
+pub struct Floor {
+    /// 0 for the ground floor.
+    pub level: u32,
+    /// Occupied grid cells, sorted.
+    pub cells: Vec<IVec2>,
+    /// Cells left without floor tiles, e.g. above stairs.
+    pub openings: Vec<IVec2>,
+}
+
+impl Floor {
+    pub fn new(level: u32, cells: impl IntoIterator<Item = IVec2>) -> Self {
+        let mut cells: Vec<_> = cells.into_iter().collect();
+        cells.sort_by_key(|c| (c.x, c.y));
+        cells.dedup();
+        Self {
+            level,
+            cells,
+            openings: vec![],
+        }
+    }
+
+    /// Cells that get floor tiles.
+    pub fn tiled_cells(&self) -> Vec<IVec2> {
+        self.cells
+            .iter()
+            .filter(|c| !self.openings.contains(c))
+            .copied()
+            .collect()
+    }
+
+    pub fn contains(&self, cell: IVec2) -> bool {
+        self.cells
+            .binary_search_by_key(&(cell.x, cell.y), |c| (c.x, c.y))
+            .is_ok()
+    }
+}
+
+/// Registry of all buildings, indexing their floors & rooms for fast point queries.
+///
+/// Kept up to date in `PostUpdate`, after transform propagation.
+#[derive(Resource, Reflect, Default)]
+#[reflect(Resource)]
+pub struct Buildings {
+    #[reflect(ignore)]
+    entries: HashMap<Entity, BuildingEntry>,
+    /// The building owning each indexed floor & room.
+    #[reflect(ignore)]
+    owners: HashMap<Entity, Entity>,
+}
+
+impl Buildings {
+    pub fn get(&self, building: Entity) -> Option<&BuildingEntry> {
+        self.entries.get(&building)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &BuildingEntry)> {
+        self.entries.iter().map(|(e, entry)| (*e, entry))
+    }
+
+    /// The building owning a floor or room.
+    pub fn owner(&self, part: Entity) -> Option<Entity> {
+        self.owners.get(&part).copied()
+    }
+
+    /// The building, floor & room containing a world space point.
+    pub fn locate(&self, point: Vec3) -> Option<BuildingLocation> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| entry.contains(point))
+            .find_map(|(building, entry)| {
+                let (floor, room) = entry.locate(point)?;
+                Some(BuildingLocation {
+                    building: *building,
+                    floor,
+                    room,
+                })
+            })
+    }
+
+    /// World space height of the floor containing a point, e.g. to stand on it.
+    pub fn floor_height_at(&self, point: Vec3) -> Option<f32> {
+        let location = self.locate(point)?;
+        let entry = self.entries.get(&location.building)?;
+        let floor = entry.floors.iter().find(|f| f.entity == location.floor)?;
+        let mut local = entry.world_to_local.transform_point3(point);
+        local.y = entry.building.floor_origin(floor.floor.level).y;
+        Some(entry.world_to_local.inverse().transform_point3(local).y)
+    }
+
+    fn insert(&mut self, building: Entity, entry: BuildingEntry) {
+        self.remove(building);
+        for floor in &entry.floors {
+            self.owners.insert(floor.entity, building);
+            for room in floor.rooms.values() {
+                self.owners.insert(*room, building);
+            }
+        }
+        self.entries.insert(building, entry);
+    }
+
+    fn remove(&mut self, building: Entity) {
+        if self.entries.remove(&building).is_some() {
+            self.owners.retain(|_, owner| *owner != building);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BuildingLocation {
+    pub building: Entity,
+    pub floor: Entity,
+    /// `None` on roofs & over openings with no room below.
+    pub room: Option<Entity>,
+}
+
+#[derive(Clone)]
+pub struct BuildingEntry {
+    pub building: Building,
+    /// World space bounds.
+    pub aabb_min: Vec3,
+    pub aabb_max: Vec3,
+    /// Floors & the roof, by level.
+    pub floors: Vec<FloorEntry>,
+    world_to_local: Affine3A,
+}
+
+impl BuildingEntry {
+    pub fn new(
+        building: Building,
+        transform: &GlobalTransform,
+        mut floors: Vec<FloorEntry>,
+    ) -> Self {
+        floors.sort_by_key(|f| f.floor.level);
+        let corners: Vec<Vec3> = floors
+            .iter()
+            .filter_map(|f| f.bounds(&building))
+            .flat_map(|(min, max)| {
+                (0..8).map(move |i| {
+                    Vec3::select(BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0), max, min)
+                })
+            })
+            .map(|p| transform.transform_point(p))
+            .collect();
+        let (aabb_min, aabb_max) = if corners.is_empty() {
+            (Vec3::NAN, Vec3::NAN)
+        } else {
+            corners
+                .iter()
+                .fold((Vec3::MAX, Vec3::MIN), |(min, max), p| {
+                    (min.min(*p), max.max(*p))
+                })
+        };
+        Self {
+            world_to_local: transform.affine().inverse(),
+            building,
+            aabb_min,
+            aabb_max,
+            floors,
+        }
+    }
+
+    /// Whether a world space point is inside the bounds.
+    pub fn contains(&self, point: Vec3) -> bool {
+        point.cmpge(self.aabb_min).all() && point.cmple(self.aabb_max).all()
+    }
+
+    /// The floor & room containing a world space point.
+    ///
+    /// Rooms are found on the highest floor whose height range contains the point,
+    /// or on the one below if it's over an opening, e.g. on stairs.
+    pub fn locate(&self, point: Vec3) -> Option<(Entity, Option<Entity>)> {
+        let local = self.world_to_local.transform_point3(point);
+        let cell = self.building.cell_at(local);
+        let mut floors = self
+            .floors
+            .iter()
+            .rev()
+            .filter(|f| {
+                let y = local.y - self.building.floor_origin(f.floor.level).y;
+                y >= -FLOOR_TOLERANCE * self.building.floor_height && y < self.building.floor_height
+            })
+            .filter(|f| f.floor.contains(cell));
+        let top = floors.next()?;
+        let room = std::iter::once(top)
+            .chain(floors)
+            .find_map(|f| Some((f.entity, *f.rooms.get(&cell)?)));
+        Some(room.map_or((top.entity, None), |(floor, room)| (floor, Some(room))))
+    }
+}
+
+/// How far below its origin a floor still contains points, relative to the floor height.
+const FLOOR_TOLERANCE: f32 = 0.1;
+
+#[derive(Clone)]
+pub struct FloorEntry {
+    pub entity: Entity,
+    pub floor: Floor,
+    /// The room of each cell.
+    pub rooms: HashMap<IVec2, Entity>,
+}
+
+impl FloorEntry {
+    /// Building space bounds, `None` if the floor has no cells.
+    pub fn bounds(&self, building: &Building) -> Option<(Vec3, Vec3)> {
+        let min = self.floor.cells.iter().copied().reduce(IVec2::min)?;
+        let max = self.floor.cells.iter().copied().reduce(IVec2::max)? + IVec2::ONE;
+        let origin = building.floor_origin(self.floor.level);
+        Some((
+            origin + building.corner(min) - Vec3::Y * FLOOR_TOLERANCE * building.floor_height,
+            origin + building.corner(max) + Vec3::Y * building.floor_height,
+        ))
+    }
+}
+
+fn update_buildings(
+    mut buildings: ResMut<Buildings>,
+    q_buildings: Query<(&Building, &GlobalTransform, &Children)>,
+    q_changed_buildings: Query<
+        Entity,
+        (
+            With<Building>,
+            Or<(
+                Changed<Building>,
+                Changed<GlobalTransform>,
+                Changed<Children>,
+            )>,
+        ),
+    >,
+    q_changed_floors: Query<&Parent, (With<Floor>, Or<(Changed<Floor>, Changed<Children>)>)>,
+    q_changed_rooms: Query<&Parent, Changed<Room>>,
+    q_floors: Query<(&Floor, Option<&Children>)>,
+    q_rooms: Query<&Room>,
+    q_parents: Query<&Parent>,
+    mut removed_buildings: RemovedComponents<Building>,
+    mut removed_floors: RemovedComponents<Floor>,
+    mut removed_rooms: RemovedComponents<Room>,
+) {
+    let mut dirty: HashSet<Entity> = q_changed_buildings.iter().collect();
+    dirty.extend(q_changed_floors.iter().map(|p| p.get()));
+    dirty.extend(
+        q_changed_rooms
+            .iter()
+            .filter_map(|floor| q_parents.get(floor.get()).ok())
+            .map(|p| p.get()),
+    );
+    dirty.extend(
+        removed_floors
+            .read()
+            .chain(removed_rooms.read())
+            .filter_map(|part| buildings.owner(part)),
+    );
+    dirty.extend(removed_buildings.read());
+
+    for building_ent in dirty {
+        let Ok((building, transform, children)) = q_buildings.get(building_ent) else {
+            buildings.remove(building_ent);
+            continue;
+        };
+        let floors = children
+            .iter()
+            .filter_map(|floor_ent| {
+                let (floor, floor_children) = q_floors.get(*floor_ent).ok()?;
+                let rooms = floor_children
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|room_ent| Some((*room_ent, q_rooms.get(*room_ent).ok()?)))
+                    .flat_map(|(room_ent, room)| room.cells.iter().map(move |c| (*c, room_ent)))
+                    .collect();
+                Some(FloorEntry {
+                    entity: *floor_ent,
+                    floor: floor.clone(),
+                    rooms,
+                })
+            })
+            .collect();
+        buildings.insert(
+            building_ent,
+            BuildingEntry::new(building.clone(), transform, floors),
+        );
+    }
+}
 
 /// Navigable element of floors (anchored). Has collider(s).
 #[derive(Component)]
-pub struct FloorTile;
+pub struct FloorTile {
+    pub cell: IVec2,
+}
 
 /// Blocking element of floors (anchored). Containes wall tiles.
 ///
 /// May also be anchored to the floor above & become tilted.
-#[derive(Component)]
-pub struct Wall;
+///
+/// Runs along grid lines, from corner `start` to corner `end`, in the +X or +Z direction.
+/// Its local X axis points along the wall.
+#[derive(Component, Clone, PartialEq, Eq, Debug)]
+pub struct Wall {
+    pub start: IVec2,
+    pub end: IVec2,
+    /// Direction away from the floor cells the wall encloses.
+    pub outward: IVec2,
+}
+
+impl Wall {
+    /// Unit step from `start` to `end`.
+    pub fn direction(&self) -> IVec2 {
+        (self.end - self.start).signum()
+    }
+
+    /// Length in cells.
+    pub fn length(&self) -> u32 {
+        (self.end - self.start).abs().max_element() as u32
+    }
+
+    /// The floor cell next to the `index`-th edge of the wall.
+    pub fn inner_cell(&self, index: u32) -> IVec2 {
+        let corner = self.start + self.direction() * index as i32;
+        // a cell is indexed by its min corner
+        if self.outward.x > 0 || self.outward.y > 0 {
+            corner - self.outward
+        } else {
+            corner
+        }
+    }
+
+    /// Transform relative to the floor.
+    pub fn transform(&self, building: &Building) -> Transform {
+        let dir = self.direction();
+        Transform::from_translation(building.corner(self.start)).with_rotation(
+            Quat::from_rotation_arc(Vec3::X, Vec3::new(dir.x as f32, 0., dir.y as f32)),
+        )
+    }
+}
+
+/// Top edge of a wall anchored to the edge of the floor above, making the wall a sloped quad.
+///
+/// In wall space, the top edge runs from `top_start` to `top_end` along X, `offset` away from the bottom edge.
+#[derive(Component, Clone, Debug)]
+pub struct WallAnchor {
+    /// The `Floor` (or `Roof`) above.
+    pub floor: Entity,
+    pub top_start: f32,
+    pub top_end: f32,
+    /// Along the wall's outward direction, negative when leaning inward.
+    pub offset: f32,
+}
+
+impl WallAnchor {
+    /// Anchors `wall` to the parallel edge of `upper_cells` that overlaps it the most.
+    pub fn fit(floor: Entity, wall: &Wall, upper_cells: &[IVec2], tile_size: f32) -> Option<Self> {
+        // (position across, range along) of a wall's line
+        let line = |w: &Wall| {
+            if w.outward.x != 0 {
+                (w.start.x, w.start.y, w.end.y)
+            } else {
+                (w.start.y, w.start.x, w.end.x)
+            }
+        };
+        let (across, start, end) = line(wall);
+        let sign = wall.outward.x + wall.outward.y;
+        let (top_across, top_start, top_end) = boundary_walls(upper_cells)
+            .iter()
+            .filter(|w| w.outward == wall.outward)
+            .map(line)
+            .filter(|&(_, a, b)| a.max(start) < b.min(end))
+            .max_by_key(|&(top_across, a, b)| {
+                (b.min(end) - a.max(start), -(top_across - across).abs())
+            })?;
+        Some(Self {
+            floor,
+            top_start: (top_start - start) as f32 * tile_size,
+            top_end: (top_end - start) as f32 * tile_size,
+            offset: ((top_across - across) * sign) as f32 * tile_size,
+        })
+    }
+
+    /// Maps a point of the unrolled wall (X along the bottom edge, Y up) onto the sloped quad, in wall space.
+    ///
+    /// Horizontal lines stay parallel to the wall, even when tapered (`top_end - top_start` differs from
+    /// the length), so mapped rectangles remain planar trapezoids.
+    pub fn map(&self, wall: &Wall, building: &Building, point: Vec2) -> Vec3 {
+        let length = wall.length() as f32 * building.tile_size;
+        let height = building.wall_height();
+        let outward = wall.transform(building).rotation.inverse()
+            * Vec3::new(wall.outward.x as f32, 0., wall.outward.y as f32);
+        let top = Vec3::new(
+            self.top_start + (self.top_end - self.top_start) * point.x / length,
+            height,
+            0.,
+        ) + outward * self.offset;
+        Vec3::new(point.x, 0., 0.).lerp(top, point.y / height)
+    }
+
+    /// Inverse of `map`: the point of the unrolled wall that maps to `point`.
+    pub fn unmap(&self, wall: &Wall, building: &Building, point: Vec3) -> Vec2 {
+        let length = wall.length() as f32 * building.tile_size;
+        let t = point.y / building.wall_height();
+        // mapped X is linear in unrolled X at a given height
+        let scale = 1. - t + t * (self.top_end - self.top_start) / length;
+        Vec2::new((point.x - t * self.top_start) / scale, point.y)
+    }
+}
 
 /// Element of walls (anchored). Has collider(s).
-#[derive(Component)]
-pub struct WallTile;
+///
+/// A box of the wall's thickness, centered on the wall's line.
+#[derive(Component, Clone, Debug)]
+pub struct WallTile {
+    /// Index of the grid edge along the wall.
+    pub index: u32,
+    /// Extent in wall space: X along the wall, Y up.
+    pub rect: Rect,
+}
 
 /// Navigation element. Has nav mesh(es). Origin at center of main entrance. Contains furniture.
-#[derive(Component)]
-pub struct Room;
+#[derive(Component, Clone)]
+pub struct Room {
+    /// Floor cells covered by the room.
+    pub cells: Vec<IVec2>,
+}
 
 /// Navigation element connecting 2 rooms (nav meshes), or 1 room & outside. Origin at center of door.
 ///
@@ -89,3 +567,777 @@ pub struct Stairs;
 /// Contains a door leading to another floor's room, or outside.
 #[derive(Component)]
 pub struct Ramp;
+
+/// Whether a room is `Stairs` or a `Ramp`, which keep their cells when the floor changes.
+pub(crate) fn is_connector(world: &World, room: Entity) -> bool {
+    world.get::<Stairs>(room).is_some() || world.get::<Ramp>(room).is_some()
+}
+
+/// Cells whose center lies inside the footprint polygon (building space XZ, in meters).
+pub fn rasterize_footprint(footprint: &[Vec2], tile_size: f32) -> Vec<IVec2> {
+    if footprint.len() < 3 {
+        return vec![];
+    }
+    let (min, max) = footprint
+        .iter()
+        .fold((Vec2::MAX, Vec2::MIN), |(min, max), p| {
+            (min.min(*p), max.max(*p))
+        });
+    let min = (min / tile_size).floor().as_ivec2();
+    let max = (max / tile_size).ceil().as_ivec2();
+
+    let mut cells = vec![];
+    for x in min.x..max.x {
+        for y in min.y..max.y {
+            let center = (Vec2::new(x as f32, y as f32) + 0.5) * tile_size;
+            // even-odd rule
+            let mut inside = false;
+            for (i, a) in footprint.iter().enumerate() {
+                let b = footprint[(i + 1) % footprint.len()];
+                if (a.y > center.y) != (b.y > center.y)
+                    && center.x < a.x + (center.y - a.y) / (b.y - a.y) * (b.x - a.x)
+                {
+                    inside = !inside;
+                }
+            }
+            if inside {
+                cells.push(IVec2::new(x, y));
+            }
+        }
+    }
+    cells
+}
+
+/// Walls around the boundary of a set of cells, merged into maximal straight runs.
+pub fn boundary_walls(cells: &[IVec2]) -> Vec<Wall> {
+    let set: HashSet<IVec2> = cells.iter().copied().collect();
+    // (outward, grid line) -> positions of the unit edges along the line
+    let mut edges: HashMap<(IVec2, i32), Vec<i32>> = HashMap::new();
+    for cell in cells {
+        for outward in [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y] {
+            if set.contains(&(*cell + outward)) {
+                continue;
+            }
+            let far_side = (outward.x > 0 || outward.y > 0) as i32;
+            let key = if outward.x != 0 {
+                ((outward, cell.x + far_side), cell.y)
+            } else {
+                ((outward, cell.y + far_side), cell.x)
+            };
+            edges.entry(key.0).or_default().push(key.1);
+        }
+    }
+
+    let mut walls = vec![];
+    for ((outward, line), mut positions) in edges {
+        positions.sort();
+        positions.dedup();
+        let mut run_start = positions[0];
+        for (i, pos) in positions.iter().enumerate() {
+            let run_ends = positions.get(i + 1) != Some(&(pos + 1));
+            if run_ends {
+                let (start, end) = if outward.x != 0 {
+                    (IVec2::new(line, run_start), IVec2::new(line, pos + 1))
+                } else {
+                    (IVec2::new(run_start, line), IVec2::new(pos + 1, line))
+                };
+                walls.push(Wall {
+                    start,
+                    end,
+                    outward,
+                });
+                if let Some(next) = positions.get(i + 1) {
+                    run_start = *next;
+                }
+            }
+        }
+    }
+    walls.sort_by_key(|w| (w.start.x, w.start.y, w.end.x, w.end.y, w.outward.x));
+    walls
+}
+
+/// Spawns a building with `floors` floors over a footprint polygon, plus a foundation & a roof.
+pub struct SpawnBuilding {
+    pub building: Building,
+    /// Polygon in building space XZ, in meters.
+    pub footprint: Vec<Vec2>,
+    pub floors: u32,
+    pub transform: Transform,
+}
+
+impl Command for SpawnBuilding {
+    fn apply(self, world: &mut World) {
+        let cells = rasterize_footprint(&self.footprint, self.building.tile_size);
+        if cells.is_empty() {
+            return;
+        }
+        let building = world
+            .spawn((
+                self.building.clone(),
+                SpatialBundle::from_transform(self.transform),
+            ))
+            .id();
+        world
+            .entity_mut(building)
+            .insert(Name::new(format!("Building ({building:?})")));
+
+        spawn_foundation(world, building, &self.building, &cells);
+        for level in 0..=self.floors {
+            let floor = world
+                .spawn((
+                    Floor::new(level, cells.iter().copied()),
+                    SpatialBundle::from_transform(Transform::from_translation(
+                        self.building.floor_origin(level),
+                    )),
+                ))
+                .set_parent(building)
+                .id();
+            if level == self.floors {
+                world
+                    .entity_mut(floor)
+                    .insert((Roof, Name::new(format!("Roof ({floor:?})"))));
+            } else {
+                world
+                    .entity_mut(floor)
+                    .insert(Name::new(format!("Floor {level} ({floor:?})")));
+            }
+            RebuildFloor.apply(floor, world);
+        }
+    }
+}
+
+/// Spawns the `Foundation` below the ground floor's cells.
+pub(crate) fn spawn_foundation(
+    world: &mut World,
+    building: Entity,
+    spec: &Building,
+    cells: &[IVec2],
+) {
+    let height = FOUNDATION_HEIGHT - SLAB_THICKNESS + FOUNDATION_DEPTH;
+    let mesh = world
+        .resource_mut::<Assets<Mesh>>()
+        .add(Mesh::from(shape::Box::new(
+            spec.tile_size,
+            height,
+            spec.tile_size,
+        )));
+    let material = world
+        .resource::<BasicMaterials>()
+        .building_foundation
+        .clone();
+    let foundation = world
+        .spawn((Foundation, SpatialBundle::default()))
+        .set_parent(building)
+        .id();
+    world
+        .entity_mut(foundation)
+        .insert(Name::new(format!("Foundation ({foundation:?})")));
+    for cell in cells {
+        world
+            .spawn((
+                PbrBundle {
+                    transform: Transform::from_translation(
+                        spec.cell_center(*cell) + Vec3::Y * (height / 2. - FOUNDATION_DEPTH),
+                    ),
+                    mesh: mesh.clone(),
+                    material: material.clone(),
+                    ..default()
+                },
+                RigidBody::Static,
+                Collider::cuboid(spec.tile_size, height, spec.tile_size),
+                CollisionLayers::new([Layer::Object], [Layer::Object]),
+            ))
+            .set_parent(foundation);
+    }
+}
+
+/// Respawns the floor tiles & walls of a `Floor` from its cells.
+/// Adds a room covering the whole floor if it has none. Roofs get no walls or rooms.
+pub struct RebuildFloor;
+
+impl EntityCommand for RebuildFloor {
+    fn apply(self, id: Entity, world: &mut World) {
+        let Some(floor) = world.get::<Floor>(id).cloned() else {
+            return;
+        };
+        let Some(building) = world
+            .get::<Parent>(id)
+            .and_then(|p| world.get::<Building>(p.get()))
+            .cloned()
+        else {
+            return;
+        };
+        let is_roof = world.get::<Roof>(id).is_some();
+
+        let children: Vec<Entity> = world
+            .get::<Children>(id)
+            .map(|c| c.to_vec())
+            .unwrap_or_default();
+        let mut has_room = false;
+        for child in children {
+            let child_ref = world.entity(child);
+            if child_ref.contains::<FloorTile>() || child_ref.contains::<Wall>() {
+                world.entity_mut(child).despawn_recursive();
+            } else if child_ref.contains::<Room>() {
+                has_room = true;
+            }
+        }
+
+        spawn_floor_tiles(world, id, &building, &floor.tiled_cells(), is_roof);
+        if is_roof {
+            return;
+        }
+        for wall in boundary_walls(&floor.cells) {
+            spawn_wall(world, id, &building, wall);
+        }
+        if !has_room {
+            let center = floor
+                .cells
+                .iter()
+                .map(|c| building.cell_center(*c))
+                .sum::<Vec3>()
+                / floor.cells.len().max(1) as f32;
+            let room = world
+                .spawn((
+                    Room {
+                        cells: floor.cells.clone(),
+                    },
+                    SpatialBundle::from_transform(Transform::from_translation(center)),
+                ))
+                .set_parent(id)
+                .id();
+            world
+                .entity_mut(room)
+                .insert(Name::new(format!("Room ({room:?})")));
+        }
+    }
+}
+
+pub(crate) fn spawn_floor_tiles(
+    world: &mut World,
+    floor_ent: Entity,
+    building: &Building,
+    cells: &[IVec2],
+    is_roof: bool,
+) {
+    let mesh = world
+        .resource_mut::<Assets<Mesh>>()
+        .add(Mesh::from(shape::Box::new(
+            building.tile_size,
+            SLAB_THICKNESS,
+            building.tile_size,
+        )));
+    let materials = world.resource::<BasicMaterials>();
+    let material = if is_roof {
+        materials.building_roof.clone()
+    } else {
+        materials.building_floor.clone()
+    };
+    for cell in cells {
+        let tile = world
+            .spawn((
+                FloorTile { cell: *cell },
+                PbrBundle {
+                    transform: Transform::from_translation(
+                        building.cell_center(*cell) - Vec3::Y * SLAB_THICKNESS / 2.,
+                    ),
+                    mesh: mesh.clone(),
+                    material: material.clone(),
+                    ..default()
+                },
+                RigidBody::Static,
+                Collider::cuboid(building.tile_size, SLAB_THICKNESS, building.tile_size),
+                CollisionLayers::new([Layer::Object], [Layer::Object]),
+            ))
+            .set_parent(floor_ent)
+            .id();
+        world
+            .entity_mut(tile)
+            .insert(Selectable::new(floor_ent, Some(tile)));
+    }
+}
+
+/// Spawns a wall with one tile per grid edge.
+pub(crate) fn spawn_wall(
+    world: &mut World,
+    floor_ent: Entity,
+    building: &Building,
+    wall: Wall,
+) -> Entity {
+    let transform = wall.transform(building);
+    let length = wall.length();
+    let wall_ent = world
+        .spawn((wall, SpatialBundle::from_transform(transform)))
+        .set_parent(floor_ent)
+        .id();
+    world
+        .entity_mut(wall_ent)
+        .insert(Name::new(format!("Wall ({wall_ent:?})")));
+    for index in 0..length {
+        let rect = Rect::new(
+            index as f32 * building.tile_size,
+            0.,
+            (index + 1) as f32 * building.tile_size,
+            building.wall_height(),
+        );
+        spawn_wall_tile(world, wall_ent, building, WallTile { index, rect });
+    }
+    wall_ent
+}
+
+pub(crate) fn spawn_wall_tile(
+    world: &mut World,
+    wall_ent: Entity,
+    building: &Building,
+    tile: WallTile,
+) -> Entity {
+    let size = tile.rect.size();
+    let center = tile.rect.center();
+    let thickness = building.wall_thickness;
+    let anchored = world
+        .get::<Wall>(wall_ent)
+        .zip(world.get::<WallAnchor>(wall_ent));
+    let (transform, mesh, collider) = if let Some((wall, anchor)) = anchored {
+        // sheared along the wall's local Z, so the faces stay planar
+        let rect = tile.rect;
+        let corners: [Vec3; 8] = std::array::from_fn(|i| {
+            let x = if i & 1 == 0 { rect.min.x } else { rect.max.x };
+            let y = if i & 2 == 0 { rect.min.y } else { rect.max.y };
+            let z = if i & 4 == 0 { -thickness } else { thickness } / 2.;
+            anchor.map(wall, building, Vec2::new(x, y)) + Vec3::Z * z
+        });
+        let collider = Collider::convex_hull(corners.to_vec())
+            .unwrap_or_else(|| Collider::cuboid(size.x, size.y, thickness));
+        (
+            Transform::IDENTITY,
+            Mesh::from(Hexahedron::new(corners)),
+            collider,
+        )
+    } else {
+        (
+            Transform::from_xyz(center.x, center.y, 0.),
+            Mesh::from(shape::Box::new(size.x, size.y, thickness)),
+            Collider::cuboid(size.x, size.y, thickness),
+        )
+    };
+    let mesh = world.resource_mut::<Assets<Mesh>>().add(mesh);
+    let material = world.resource::<BasicMaterials>().building_wall.clone();
+    let tile_ent = world
+        .spawn((
+            tile,
+            PbrBundle {
+                transform,
+                mesh,
+                material,
+                ..default()
+            },
+            RigidBody::Static,
+            collider,
+            CollisionLayers::new([Layer::Object], [Layer::Object]),
+        ))
+        .set_parent(wall_ent)
+        .id();
+    world
+        .entity_mut(tile_ent)
+        .insert(Selectable::new(wall_ent, Some(tile_ent)));
+    tile_ent
+}
+
+/// Anchors the top of a `Wall` to the floor above, or makes it vertical again if `floor` is `None`.
+///
+/// Wall tiles keep their unrolled rects & are reshaped to fit.
+pub struct AnchorWall {
+    pub floor: Option<Entity>,
+}
+
+impl EntityCommand for AnchorWall {
+    fn apply(self, id: Entity, world: &mut World) {
+        let Some(wall) = world.get::<Wall>(id).cloned() else {
+            return;
+        };
+        let Some(building) = world
+            .get::<Parent>(id)
+            .and_then(|p| world.get::<Parent>(p.get()))
+            .and_then(|p| world.get::<Building>(p.get()))
+            .cloned()
+        else {
+            return;
+        };
+        let anchor = self.floor.and_then(|floor| {
+            let upper = world.get::<Floor>(floor)?;
+            WallAnchor::fit(floor, &wall, &upper.cells, building.tile_size)
+        });
+        match anchor {
+            Some(anchor) => world.entity_mut(id).insert(anchor),
+            None => world.entity_mut(id).remove::<WallAnchor>(),
+        };
+
+        let tiles: Vec<(Entity, WallTile)> = world
+            .get::<Children>(id)
+            .into_iter()
+            .flatten()
+            .filter_map(|c| Some((*c, world.get::<WallTile>(*c)?.clone())))
+            .collect();
+        for (tile_ent, tile) in tiles {
+            world.entity_mut(tile_ent).despawn_recursive();
+            spawn_wall_tile(world, id, &building, tile);
+        }
+    }
+}
+
+fn update_wall_anchors(
+    q_anchors: Query<(Entity, &WallAnchor)>,
+    q_changed: Query<(), Changed<Floor>>,
+    mut cmd: Commands,
+) {
+    for (wall, anchor) in &q_anchors {
+        if q_changed.contains(anchor.floor) {
+            cmd.entity(wall).add(AnchorWall {
+                floor: Some(anchor.floor),
+            });
+        }
+    }
+}
+
+fn anchor_wall(
+    mouse: Res<Input<MouseButton>>,
+    spatial_query: SpatialQuery,
+    panel: Res<SidePanel>,
+    q_camera: Query<&MainCamera>,
+    q_wall_tile: Query<&Parent, With<WallTile>>,
+    q_walls: Query<(&Parent, Has<WallAnchor>), With<Wall>>,
+    q_floors: Query<(Entity, &Parent, &Floor)>,
+    mut cmd: Commands,
+) {
+    if panel.mode != UiMode::AnchorWall
+        || panel.mouse_over
+        || !mouse.just_pressed(MouseButton::Left)
+    {
+        return;
+    };
+    let Ok(Some(ray)) = q_camera.get_single().map(|c| c.mouse_ray) else {
+        return;
+    };
+    let Some(hit) = spatial_query.cast_ray(
+        ray.origin,
+        ray.direction,
+        1000.,
+        false,
+        SpatialQueryFilter::new().with_masks([Layer::Object]),
+    ) else {
+        return;
+    };
+    let Ok(wall) = q_wall_tile.get(hit.entity).map(|p| p.get()) else {
+        return;
+    };
+    let Ok((floor, anchored)) = q_walls.get(wall) else {
+        return;
+    };
+    if anchored {
+        cmd.entity(wall).add(AnchorWall { floor: None });
+        return;
+    }
+    let Ok((_, building, lower)) = q_floors.get(floor.get()) else {
+        return;
+    };
+    let upper = q_floors
+        .iter()
+        .find(|(_, parent, f)| parent.get() == building.get() && f.level == lower.level + 1)
+        .map(|(e, ..)| e);
+    if upper.is_some() {
+        cmd.entity(wall).add(AnchorWall { floor: upper });
+    }
+}
+
+/// Places a door on a `WallTile`, splitting the tile into the pieces left of, right of & above the door.
+/// Only tiles starting at the floor can take a door.
+///
+/// The door links the rooms on both sides of the wall, or the inner room & outside on the ground floor.
+pub struct AddDoor {
+    pub width: f32,
+    pub height: f32,
+    /// Center of the door along the wall, in wall space. Clamped so the door fits the tile.
+    pub center: f32,
+}
+
+impl EntityCommand for AddDoor {
+    fn apply(self, id: Entity, world: &mut World) {
+        let Some(tile) = world.get::<WallTile>(id).cloned() else {
+            return;
+        };
+        let Some(wall_ent) = world.get::<Parent>(id).map(|p| p.get()) else {
+            return;
+        };
+        let Some(wall) = world.get::<Wall>(wall_ent).cloned() else {
+            return;
+        };
+        let Some(floor_ent) = world.get::<Parent>(wall_ent).map(|p| p.get()) else {
+            return;
+        };
+        let Some(building) = world
+            .get::<Parent>(floor_ent)
+            .and_then(|p| world.get::<Building>(p.get()))
+            .cloned()
+        else {
+            return;
+        };
+
+        let rect = tile.rect;
+        if rect.min.y > 0. {
+            // doors stand on the floor, not on the piece above another door
+            return;
+        }
+        let width = self.width.min(rect.width());
+        let height = self.height.min(rect.height());
+        let center = self
+            .center
+            .clamp(rect.min.x + width / 2., rect.max.x - width / 2.);
+        let door_rect = Rect::new(
+            center - width / 2.,
+            rect.min.y,
+            center + width / 2.,
+            rect.min.y + height,
+        );
+
+        let inner = wall.inner_cell(tile.index);
+        let room_at = |world: &World, cell: IVec2| {
+            world.get::<Children>(floor_ent).and_then(|children| {
+                children.iter().copied().find(|c| {
+                    !is_connector(world, *c)
+                        && world
+                            .get::<Room>(*c)
+                            .is_some_and(|room| room.cells.contains(&cell))
+                })
+            })
+        };
+        let inside = room_at(world, inner);
+        let outside = room_at(world, inner + wall.outward);
+        let (inside, outside) = match (inside, outside) {
+            (Some(inside), outside) => (inside, outside),
+            (None, Some(outside)) => (outside, None),
+            (None, None) => return,
+        };
+        let level = world.get::<Floor>(floor_ent).map_or(0, |f| f.level);
+        if outside.is_none() && level > 0 {
+            warn!("Doors leading outside must be on the ground floor");
+            return;
+        }
+
+        world.entity_mut(id).despawn_recursive();
+        let pieces = [
+            Rect::new(rect.min.x, rect.min.y, door_rect.min.x, rect.max.y),
+            Rect::new(door_rect.max.x, rect.min.y, rect.max.x, rect.max.y),
+            Rect::new(
+                door_rect.min.x,
+                door_rect.max.y,
+                door_rect.max.x,
+                rect.max.y,
+            ),
+        ];
+        for rect in pieces {
+            if rect.width() > 0.01 && rect.height() > 0.01 {
+                spawn_wall_tile(
+                    world,
+                    wall_ent,
+                    &building,
+                    WallTile {
+                        index: tile.index,
+                        rect,
+                    },
+                );
+            }
+        }
+
+        let door_center = door_rect.center();
+        let door_pos = world
+            .get::<WallAnchor>(wall_ent)
+            .map_or(Vec3::new(door_center.x, door_center.y, 0.), |anchor| {
+                anchor.map(&wall, &building, door_center)
+            });
+        let door = world
+            .spawn((
+                Door {
+                    outside,
+                    inside,
+                    width,
+                    height,
+                    opened: true,
+                },
+                SpatialBundle::from_transform(Transform::from_translation(door_pos)),
+            ))
+            .set_parent(wall_ent)
+            .id();
+        world
+            .entity_mut(door)
+            .insert(Name::new(format!("Door ({door:?})")));
+    }
+}
+
+/// How often door openings are probed for obstacles, in seconds.
+const DOOR_PROBE_INTERVAL: f32 = 0.5;
+/// Depth of the volume probed through a door opening.
+const DOOR_PROBE_DEPTH: f32 = 0.6;
+
+#[derive(Resource)]
+struct DoorProbeTimer(Timer);
+
+impl Default for DoorProbeTimer {
+    fn default() -> Self {
+        Self(Timer::from_seconds(
+            DOOR_PROBE_INTERVAL,
+            TimerMode::Repeating,
+        ))
+    }
+}
+
+/// Sent when `Door::opened` changes.
+#[derive(Event)]
+pub struct DoorChangedEvent {
+    pub door: Entity,
+    pub opened: bool,
+}
+
+fn probe_doors(
+    time: Res<Time>,
+    mut timer: ResMut<DoorProbeTimer>,
+    spatial_query: SpatialQuery,
+    mut q_doors: Query<(Entity, &mut Door, &GlobalTransform)>,
+    q_structure: Query<(), Or<(With<WallTile>, With<FloorTile>, With<ConnectorPart>)>>,
+    q_agents: Query<(), With<BuildingAgent>>,
+    mut ev_door_changed: EventWriter<DoorChangedEvent>,
+) {
+    if !timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+    for (door_ent, mut door, gtr) in &mut q_doors {
+        let (_, rotation, translation) = gtr.to_scale_rotation_translation();
+        // a bit smaller than the opening, so the surrounding wall & floor tiles are not hit
+        let probe = Collider::cuboid(door.width * 0.9, door.height * 0.9, DOOR_PROBE_DEPTH);
+        let opened = spatial_query
+            .shape_intersections(
+                &probe,
+                translation,
+                rotation,
+                SpatialQueryFilter::new().with_masks([Layer::Object]),
+            )
+            .into_iter()
+            // agents walking through don't close the door behind them
+            .all(|e| q_structure.contains(e) || q_agents.contains(e));
+        if opened != door.opened {
+            door.opened = opened;
+            ev_door_changed.send(DoorChangedEvent {
+                door: door_ent,
+                opened,
+            });
+        }
+    }
+}
+
+fn add_door(
+    mouse: Res<Input<MouseButton>>,
+    spatial_query: SpatialQuery,
+    panel: Res<SidePanel>,
+    q_camera: Query<&MainCamera>,
+    q_wall_tile: Query<(&Parent, &WallTile)>,
+    q_gtr: Query<&GlobalTransform>,
+    mut cmd: Commands,
+) {
+    if panel.mode != UiMode::AddDoor || panel.mouse_over || !mouse.just_pressed(MouseButton::Left) {
+        return;
+    };
+    let Ok(Some(ray)) = q_camera.get_single().map(|c| c.mouse_ray) else {
+        return;
+    };
+    let Some(hit) = spatial_query.cast_ray(
+        ray.origin,
+        ray.direction,
+        1000.,
+        false,
+        SpatialQueryFilter::new().with_masks([Layer::Object]),
+    ) else {
+        return;
+    };
+    let Ok((parent, _)) = q_wall_tile.get(hit.entity) else {
+        return;
+    };
+    let Ok(wall_gtr) = q_gtr.get(parent.get()) else {
+        return;
+    };
+    let pos = ray.origin + hit.time_of_impact * ray.direction;
+    let local = wall_gtr.affine().inverse().transform_point3(pos);
+    cmd.entity(hit.entity).add(AddDoor {
+        width: panel.door_width,
+        height: panel.door_height,
+        center: local.x,
+    });
+}
+
+fn draw_doors(mut gizmos: Gizmos, q_doors: Query<(&Door, &GlobalTransform)>) {
+    for (door, gtr) in &q_doors {
+        let (_, rotation, translation) = gtr.to_scale_rotation_translation();
+        let color = if door.opened {
+            Color::GREEN
+        } else {
+            Color::RED
+        };
+        gizmos.rect(
+            translation,
+            rotation,
+            Vec2::new(door.width, door.height),
+            color,
+        );
+    }
+}
+
+fn add_building(
+    mouse: Res<Input<MouseButton>>,
+    spatial_query: SpatialQuery,
+    panel: Res<SidePanel>,
+    terrain: Res<Terrain>,
+    q_camera: Query<&MainCamera>,
+    mut cmd: Commands,
+) {
+    if panel.mode != UiMode::AddBuilding
+        || panel.mouse_over
+        || !mouse.just_pressed(MouseButton::Left)
+    {
+        return;
+    };
+    let Ok(Some(ray)) = q_camera.get_single().map(|c| c.mouse_ray) else {
+        return;
+    };
+    let Some(hit) = spatial_query.cast_ray(
+        ray.origin,
+        ray.direction,
+        1000.,
+        false,
+        SpatialQueryFilter::new().with_masks([Layer::Object]),
+    ) else {
+        return;
+    };
+    if !terrain.is_ground(hit.entity) {
+        return;
+    }
+    let pos = ray.origin + hit.time_of_impact * ray.direction;
+    // an L shaped footprint, centered on the clicked point
+    let footprint = [
+        Vec2::new(0., 0.),
+        Vec2::new(12., 0.),
+        Vec2::new(12., 6.),
+        Vec2::new(6., 6.),
+        Vec2::new(6., 12.),
+        Vec2::new(0., 12.),
+    ]
+    .map(|p| p - Vec2::splat(6.))
+    .to_vec();
+    cmd.add(SpawnBuilding {
+        building: Building {
+            tile_size: 2.,
+            floor_height: 3.,
+            wall_thickness: 0.2,
+        },
+        footprint,
+        floors: panel.building_floors as u32,
+        transform: Transform::from_translation(pos),
+    });
+}
@@ -0,0 +1,863 @@
+use std::ops::Range;
+
+use bevy::{
+    ecs::system::EntityCommand,
+    pbr::{NotShadowCaster, NotShadowReceiver},
+    prelude::*,
+    render::view::RenderLayers,
+    utils::HashSet,
+};
+use bevy_xpbd_3d::prelude::*;
+
+use crate::{
+    camera::{MainCamera, UI_CAMERA_LAYER},
+    ui::{
+        basic_materials::BasicMaterials,
+        selection::{Layer, Selected, SelectionUiState},
+        transform_gizmo::{
+            GizmoAxis, GizmoConstraint, TransformGizmoMeshes, BAR_H, BAR_W, CONE_H, CONE_R,
+        },
+    },
+};
+
+use super::{
+    building::{
+        boundary_walls, is_connector, spawn_floor_tiles, spawn_foundation, spawn_wall, AddDoor,
+        AnchorWall, Building, Door, Floor, FloorTile, Foundation, Roof, Room, Wall, WallAnchor,
+        WallTile,
+    },
+    building_stairs::connector_footprint,
+};
+
+pub struct BuildingEditPlugin;
+
+impl Plugin for BuildingEditPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EditHandleDrag>().add_systems(
+            Update,
+            (update_edit_handles, place_edit_handles, drag_edit_handles).chain(),
+        );
+    }
+}
+
+/// Grows (`steps > 0`) or trims (`steps < 0`) the side of a cell set facing `side`.
+///
+/// Growing extrudes the cells on the edge, trimming removes whole rows. Never trims all the cells.
+pub fn resize_cells(cells: &[IVec2], side: IVec2, steps: i32) -> Vec<IVec2> {
+    let Some(edge) = cells.iter().map(|c| c.dot(side)).max() else {
+        return vec![];
+    };
+    let mut result: Vec<IVec2> = if steps >= 0 {
+        cells
+            .iter()
+            .copied()
+            .chain(
+                cells
+                    .iter()
+                    .filter(|c| c.dot(side) == edge)
+                    .flat_map(|c| (1..=steps).map(move |i| *c + side * i)),
+            )
+            .collect()
+    } else {
+        cells
+            .iter()
+            .filter(|c| c.dot(side) <= edge + steps)
+            .copied()
+            .collect()
+    };
+    if result.is_empty() {
+        result = cells.to_vec();
+    }
+    result
+}
+
+/// Replaces the cells of a `Floor`, respawning only the floor tiles & walls that changed.
+///
+/// Unchanged walls keep their tiles & doors, new walls the `WallAnchor` of the wall they replace.
+/// New cells join the room of a neighbouring cell, other than `Stairs` & `Ramp`s.
+/// The foundation follows the ground floor. Refused if it would cut through stairs or a ramp.
+pub struct SetFloorCells {
+    pub cells: Vec<IVec2>,
+}
+
+impl EntityCommand for SetFloorCells {
+    fn apply(self, id: Entity, world: &mut World) {
+        let Some(old) = world.get::<Floor>(id).cloned() else {
+            return;
+        };
+        let Some(building_ent) = world.get::<Parent>(id).map(|p| p.get()) else {
+            return;
+        };
+        let Some(building) = world.get::<Building>(building_ent).cloned() else {
+            return;
+        };
+        let mut floor = Floor::new(old.level, self.cells);
+        if floor.cells == old.cells {
+            return;
+        }
+        if cuts_connector(world, id, &floor) {
+            warn!("Can't trim the cells of stairs or ramps");
+            return;
+        }
+        floor.openings = old
+            .openings
+            .iter()
+            .filter(|c| floor.contains(**c))
+            .copied()
+            .collect();
+        let is_roof = world.get::<Roof>(id).is_some();
+        let added: Vec<IVec2> = floor
+            .cells
+            .iter()
+            .filter(|c| !old.contains(**c))
+            .copied()
+            .collect();
+
+        let new_walls = if is_roof {
+            vec![]
+        } else {
+            boundary_walls(&floor.cells)
+        };
+        let mut kept_walls = vec![];
+        // anchored walls that are replaced, & the floor they lean against
+        let mut anchors = vec![];
+        // doors of replaced walls: (old wall, center along the wall's line, width, height)
+        let mut doors = vec![];
+        // wall space X to position along the wall's line
+        let along = |w: &Wall, x: f32| w.start.dot(w.direction()) as f32 * building.tile_size + x;
+        let mut rooms = vec![];
+        let children: Vec<Entity> = world
+            .get::<Children>(id)
+            .map(|c| c.to_vec())
+            .unwrap_or_default();
+        for child in children {
+            let child_ref = world.entity(child);
+            if let Some(tile) = child_ref.get::<FloorTile>() {
+                if !floor.contains(tile.cell) {
+                    world.entity_mut(child).despawn_recursive();
+                }
+            } else if let Some(wall) = child_ref.get::<Wall>() {
+                if new_walls.contains(wall) {
+                    kept_walls.push(wall.clone());
+                } else {
+                    let anchor = child_ref.get::<WallAnchor>();
+                    if let Some(anchor) = anchor {
+                        anchors.push((wall.clone(), anchor.floor));
+                    }
+                    for door_ent in child_ref.get::<Children>().into_iter().flatten() {
+                        let (Some(door), Some(transform)) = (
+                            world.get::<Door>(*door_ent),
+                            world.get::<Transform>(*door_ent),
+                        ) else {
+                            continue;
+                        };
+                        let center = anchor.map_or(transform.translation.x, |anchor| {
+                            anchor.unmap(wall, &building, transform.translation).x
+                        });
+                        doors.push((wall.clone(), along(wall, center), door.width, door.height));
+                    }
+                    world.entity_mut(child).despawn_recursive();
+                }
+            } else if child_ref.contains::<Room>() {
+                rooms.push(child);
+            }
+        }
+
+        spawn_floor_tiles(world, id, &building, &added, is_roof);
+        // (outward, position across) of a wall's line
+        let line = |w: &Wall| (w.outward, w.start.dot(w.outward.abs()));
+        let mut spawned_walls = vec![];
+        for wall in new_walls {
+            if !kept_walls.contains(&wall) {
+                let anchor = anchors
+                    .iter()
+                    .find(|(old, _)| line(old) == line(&wall))
+                    .map(|(_, floor)| *floor);
+                let wall_ent = spawn_wall(world, id, &building, wall.clone());
+                if anchor.is_some() {
+                    AnchorWall { floor: anchor }.apply(wall_ent, world);
+                }
+                spawned_walls.push((wall, wall_ent));
+            }
+        }
+
+        // trimmed cells leave their rooms, added cells flood fill from their neighbours
+        let mut room_cells: Vec<Vec<IVec2>> = rooms
+            .iter()
+            .map(|room| {
+                world.get::<Room>(*room).map_or(vec![], |r| {
+                    r.cells
+                        .iter()
+                        .filter(|c| floor.contains(**c))
+                        .copied()
+                        .collect()
+                })
+            })
+            .collect();
+        let growable: Vec<usize> = (0..rooms.len())
+            .filter(|i| !is_connector(world, rooms[*i]))
+            .collect();
+        let mut pending: HashSet<IVec2> = added.into_iter().collect();
+        while !pending.is_empty() {
+            let mut assigned = vec![];
+            for cell in &pending {
+                let neighbour_room = [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y]
+                    .iter()
+                    .find_map(|d| {
+                        growable
+                            .iter()
+                            .copied()
+                            .find(|r| room_cells[*r].contains(&(*cell + *d)))
+                    });
+                if let Some(room) = neighbour_room {
+                    assigned.push((*cell, room));
+                }
+            }
+            if assigned.is_empty() {
+                if let Some(room) = growable.first() {
+                    room_cells[*room].extend(pending.drain());
+                }
+                break;
+            }
+            for (cell, room) in assigned {
+                pending.remove(&cell);
+                room_cells[room].push(cell);
+            }
+        }
+        for (room, cells) in rooms.into_iter().zip(room_cells) {
+            if cells.is_empty() {
+                world.entity_mut(room).despawn_recursive();
+            } else if let Some(mut room) = world.get_mut::<Room>(room) {
+                room.cells = cells;
+            }
+        }
+
+        // doors move to the wall that replaced theirs, if it still spans them
+        for (old, center, width, height) in doors {
+            let Some((wall, wall_ent)) = spawned_walls.iter().find(|(wall, _)| {
+                line(wall) == line(&old)
+                    && along(wall, 0.) <= center
+                    && center <= along(wall, wall.length() as f32 * building.tile_size)
+            }) else {
+                continue;
+            };
+            let center = center - along(wall, 0.);
+            let tile = world
+                .get::<Children>(*wall_ent)
+                .into_iter()
+                .flatten()
+                .copied()
+                .find(|c| {
+                    world.get::<WallTile>(*c).is_some_and(|tile| {
+                        tile.rect.min.y <= 0.
+                            && tile.rect.min.x <= center
+                            && center <= tile.rect.max.x
+                    })
+                });
+            if let Some(tile) = tile {
+                AddDoor {
+                    width,
+                    height,
+                    center,
+                }
+                .apply(tile, world);
+            }
+        }
+
+        if floor.level == 0 && !is_roof {
+            let foundations: Vec<Entity> = world
+                .get::<Children>(building_ent)
+                .into_iter()
+                .flatten()
+                .filter(|c| world.get::<Foundation>(**c).is_some())
+                .copied()
+                .collect();
+            for foundation in foundations {
+                world.entity_mut(foundation).despawn_recursive();
+            }
+            spawn_foundation(world, building_ent, &building, &floor.cells);
+        }
+
+        world.entity_mut(id).insert(floor);
+    }
+}
+
+/// Resizes one side of a `Floor` by whole cells, optionally also resizing the floors above.
+pub struct ResizeFloor {
+    pub side: IVec2,
+    pub steps: i32,
+    pub cascade: bool,
+}
+
+impl EntityCommand for ResizeFloor {
+    fn apply(self, id: Entity, world: &mut World) {
+        let Some(level) = world.get::<Floor>(id).map(|f| f.level) else {
+            return;
+        };
+        let mut floors = vec![id];
+        if self.cascade {
+            floors.extend(floors_above(world, id, level));
+        }
+        let resized: Vec<(Entity, Vec<IVec2>)> = floors
+            .into_iter()
+            .filter_map(|floor| {
+                let cells = &world.get::<Floor>(floor)?.cells;
+                Some((floor, resize_cells(cells, self.side, self.steps)))
+            })
+            .collect();
+        // all floors or none
+        if resized.iter().any(|(floor, cells)| {
+            let level = world.get::<Floor>(*floor).map_or(0, |f| f.level);
+            cuts_connector(world, *floor, &Floor::new(level, cells.iter().copied()))
+        }) {
+            warn!("Can't trim the cells of stairs or ramps");
+            return;
+        }
+        for (floor, cells) in resized {
+            SetFloorCells { cells }.apply(floor, world);
+        }
+    }
+}
+
+/// Extrudes the cells inside an outer `Wall` outward by `steps` cells, optionally also on the floors above.
+///
+/// Only the wall edges in `range` are extruded. The wall is replaced by the new outer walls.
+pub struct ExtrudeWall {
+    pub steps: u32,
+    pub range: Range<u32>,
+    pub cascade: bool,
+}
+
+impl EntityCommand for ExtrudeWall {
+    fn apply(self, id: Entity, world: &mut World) {
+        let Some(wall) = world.get::<Wall>(id).cloned() else {
+            return;
+        };
+        let Some(floor_ent) = world.get::<Parent>(id).map(|p| p.get()) else {
+            return;
+        };
+        let Some(level) = world.get::<Floor>(floor_ent).map(|f| f.level) else {
+            return;
+        };
+        let added = extrude_wall_cells(&wall, self.range, self.steps);
+        let mut floors = vec![floor_ent];
+        if self.cascade {
+            floors.extend(floors_above(world, floor_ent, level));
+        }
+        for floor in floors {
+            let Some(cells) = world.get::<Floor>(floor).map(|f| f.cells.clone()) else {
+                continue;
+            };
+            SetFloorCells {
+                cells: cells.into_iter().chain(added.iter().copied()).collect(),
+            }
+            .apply(floor, world);
+        }
+    }
+}
+
+/// The cells covered by dragging the edges in `range` of a wall `steps` cells outward.
+pub fn extrude_wall_cells(wall: &Wall, range: Range<u32>, steps: u32) -> Vec<IVec2> {
+    (range.start..range.end.min(wall.length()))
+        .flat_map(|i| {
+            let inner = wall.inner_cell(i);
+            (1..=steps as i32).map(move |k| inner + wall.outward * k)
+        })
+        .collect()
+}
+
+/// Whether `floor` would drop cells the stairs & ramps of `floor_ent` need.
+fn cuts_connector(world: &World, floor_ent: Entity, floor: &Floor) -> bool {
+    connector_footprint(world, floor_ent)
+        .iter()
+        .any(|c| !floor.contains(*c))
+}
+
+fn floors_above(world: &World, floor: Entity, level: u32) -> Vec<Entity> {
+    world
+        .get::<Parent>(floor)
+        .and_then(|p| world.get::<Children>(p.get()))
+        .map(|c| c.to_vec())
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|e| world.get::<Floor>(*e).is_some_and(|f| f.level > level))
+        .collect()
+}
+
+/// Drag handle on one side of a selected `Floor`, or on a selected outer `Wall`.
+///
+/// Walls also get a `trim` handle on each end, picking the edges to extrude.
+#[derive(Component)]
+struct EditHandle {
+    floor: Entity,
+    /// The wall to extrude, `None` to resize the floor.
+    wall: Option<Entity>,
+    side: IVec2,
+    /// Cells the side is currently dragged by.
+    steps: i32,
+    /// The wall edges to extrude.
+    range: Range<u32>,
+    /// Moves the end of `range` facing `side` instead of extruding.
+    trim: bool,
+}
+
+#[derive(Component)]
+struct EditHandlePart {
+    material: Handle<StandardMaterial>,
+    highlighted: bool,
+}
+
+#[derive(Resource, Default)]
+struct EditHandleDrag {
+    handle: Option<Entity>,
+    start: Vec3,
+}
+
+const HANDLE_SIDES: [IVec2; 4] = [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y];
+
+fn update_edit_handles(
+    meshes: Res<TransformGizmoMeshes>,
+    materials: Res<BasicMaterials>,
+    q_new_floors: Query<Entity, (With<Floor>, Added<Selected>)>,
+    q_new_walls: Query<(Entity, &Wall, &Parent), Added<Selected>>,
+    q_selected: Query<(), With<Selected>>,
+    q_handles: Query<(Entity, &EditHandle)>,
+    mut cmd: Commands,
+) {
+    for (handle_ent, handle) in &q_handles {
+        if !q_selected.contains(handle.wall.unwrap_or(handle.floor)) {
+            cmd.entity(handle_ent).despawn_recursive();
+        }
+    }
+    for floor in &q_new_floors {
+        for side in HANDLE_SIDES {
+            let material = if side.x != 0 {
+                &materials.ui_red
+            } else {
+                &materials.ui_blue
+            };
+            let handle = EditHandle {
+                floor,
+                wall: None,
+                side,
+                steps: 0,
+                range: 0..0,
+                trim: false,
+            };
+            spawn_edit_handle(&mut cmd, &meshes, material, handle);
+        }
+    }
+    for (wall_ent, wall, parent) in &q_new_walls {
+        let dir = wall.direction();
+        for (side, trim) in [(wall.outward, false), (-dir, true), (dir, true)] {
+            let handle = EditHandle {
+                floor: parent.get(),
+                wall: Some(wall_ent),
+                side,
+                steps: 0,
+                range: 0..wall.length(),
+                trim,
+            };
+            let material = if trim {
+                &materials.ui_blue
+            } else {
+                &materials.ui_green
+            };
+            spawn_edit_handle(&mut cmd, &meshes, material, handle);
+        }
+    }
+}
+
+fn spawn_edit_handle(
+    cmd: &mut Commands,
+    meshes: &TransformGizmoMeshes,
+    material: &Handle<StandardMaterial>,
+    handle: EditHandle,
+) {
+    // arrow along local +X, like the transform gizmo's axis handles
+    let rot = Quat::from_rotation_z(-std::f32::consts::FRAC_PI_2);
+    let target = handle.wall.unwrap_or(handle.floor);
+    cmd.spawn((
+        SpatialBundle::default(),
+        handle,
+        Name::new(format!("Edit handle (@{target:?})")),
+    ))
+    .with_children(|parent| {
+        for (mesh, translation, collider) in [
+            (
+                meshes.bar.clone(),
+                BAR_H / 2.,
+                Collider::cylinder(BAR_H / 2., BAR_W),
+            ),
+            (
+                meshes.cone.clone(),
+                BAR_H + CONE_H / 2.,
+                Collider::cone(CONE_H / 2., CONE_R),
+            ),
+        ] {
+            parent.spawn((
+                PbrBundle {
+                    transform: Transform::from_translation(translation * Vec3::X)
+                        .with_rotation(rot),
+                    mesh,
+                    material: material.clone(),
+                    ..default()
+                },
+                NotShadowCaster,
+                NotShadowReceiver,
+                RenderLayers::layer(UI_CAMERA_LAYER),
+                collider,
+                Sensor,
+                CollisionLayers::new([Layer::Sensor], []),
+                EditHandlePart {
+                    material: material.clone(),
+                    highlighted: false,
+                },
+            ));
+        }
+    });
+}
+
+fn place_edit_handles(
+    q_floors: Query<(&Floor, &GlobalTransform, &Parent)>,
+    q_walls: Query<&Wall>,
+    q_buildings: Query<&Building>,
+    mut q_handles: Query<(&EditHandle, &mut Transform)>,
+) {
+    for (handle, mut tr) in &mut q_handles {
+        let Ok((floor, floor_gtr, parent)) = q_floors.get(handle.floor) else {
+            continue;
+        };
+        let Ok(building) = q_buildings.get(parent.get()) else {
+            continue;
+        };
+        let side = Vec3::new(handle.side.x as f32, 0., handle.side.y as f32);
+        let edge = if let Some(wall) = handle.wall {
+            let Ok(wall) = q_walls.get(wall) else {
+                continue;
+            };
+            let corner = |i: u32| building.corner(wall.start + wall.direction() * i as i32);
+            let (start, end) = (corner(handle.range.start), corner(handle.range.end));
+            let middle = Vec3::Y * building.wall_height() / 2.;
+            if !handle.trim {
+                (start + end) / 2. + middle
+            } else {
+                // next to the wall, on its end of the range
+                let outward = Vec3::new(wall.outward.x as f32, 0., wall.outward.y as f32);
+                let end = if handle.side == wall.direction() {
+                    end
+                } else {
+                    start
+                };
+                end + middle + outward * 0.3
+            }
+        } else {
+            let (Some(min), Some(max)) = (
+                floor.cells.iter().copied().reduce(IVec2::min),
+                floor.cells.iter().copied().reduce(IVec2::max),
+            ) else {
+                continue;
+            };
+            let center = (building.corner(min) + building.corner(max + IVec2::ONE)) / 2.;
+            let extent = building.corner(max + IVec2::ONE - min) / 2.;
+            center + side * extent.dot(side.abs()) + Vec3::Y * 0.5
+        };
+        let pos = edge + side * (handle.steps as f32 * building.tile_size + 0.2);
+        let (_, floor_rot, _) = floor_gtr.to_scale_rotation_translation();
+        tr.translation = floor_gtr.transform_point(pos);
+        // about Y, as the arc from X to -X is ambiguous & can flip the handle over
+        tr.rotation = floor_rot * Quat::from_rotation_y((-side.z).atan2(side.x));
+    }
+}
+
+fn drag_edit_handles(
+    mouse: Res<Input<MouseButton>>,
+    spatial_query: SpatialQuery,
+    selection: Res<SelectionUiState>,
+    ui_materials: Res<BasicMaterials>,
+    mut drag: ResMut<EditHandleDrag>,
+    q_camera: Query<&MainCamera>,
+    q_floors: Query<(&GlobalTransform, &Parent), With<Floor>>,
+    q_buildings: Query<&Building>,
+    q_walls: Query<&Wall>,
+    mut q_handles: Query<(&mut EditHandle, &GlobalTransform)>,
+    mut q_parts: Query<(
+        Entity,
+        &Parent,
+        &mut Handle<StandardMaterial>,
+        &mut EditHandlePart,
+    )>,
+    mut cmd: Commands,
+) {
+    let Ok(Some(ray)) = q_camera.get_single().map(|c| c.mouse_ray) else {
+        return;
+    };
+
+    if let Some(handle_ent) = drag.handle {
+        let Ok((mut handle, handle_gtr)) = q_handles.get_mut(handle_ent) else {
+            drag.handle = None;
+            return;
+        };
+        let Ok((floor_gtr, parent)) = q_floors.get(handle.floor) else {
+            return;
+        };
+        let Ok(building) = q_buildings.get(parent.get()) else {
+            return;
+        };
+        let axis = if handle.side.x != 0 {
+            GizmoAxis::X
+        } else {
+            GizmoAxis::Z
+        };
+        let dir = axis.axis(floor_gtr) * (handle.side.x + handle.side.y) as f32;
+        if let Some(hit) =
+            GizmoConstraint::Axis(axis).ray_cast(handle_gtr.translation(), &ray, floor_gtr)
+        {
+            handle.steps = ((hit - drag.start).dot(dir) / building.tile_size).round() as i32;
+            if let Some(wall) = handle.wall.and_then(|w| q_walls.get(w).ok()) {
+                let (start, end) = (handle.range.start as i32, handle.range.end as i32);
+                // keep at least one edge, inside the wall
+                handle.steps = match (handle.trim, handle.side == wall.direction()) {
+                    (false, _) => handle.steps.max(0),
+                    (true, true) => handle
+                        .steps
+                        .clamp(start + 1 - end, wall.length() as i32 - end),
+                    (true, false) => handle.steps.clamp(start + 1 - end, start),
+                };
+            }
+        }
+        if !mouse.pressed(MouseButton::Left) {
+            let mut trimmed = None;
+            match handle.wall {
+                _ if handle.steps == 0 => {}
+                Some(wall) if handle.trim => {
+                    let mut range = handle.range.clone();
+                    if q_walls
+                        .get(wall)
+                        .is_ok_and(|w| handle.side == w.direction())
+                    {
+                        range.end = (range.end as i32 + handle.steps) as u32;
+                    } else {
+                        range.start = (range.start as i32 - handle.steps) as u32;
+                    }
+                    trimmed = Some((wall, range));
+                }
+                Some(wall) => {
+                    cmd.entity(wall).add(ExtrudeWall {
+                        steps: handle.steps as u32,
+                        range: handle.range.clone(),
+                        cascade: selection.resize_floors_above,
+                    });
+                }
+                None => {
+                    cmd.entity(handle.floor).add(ResizeFloor {
+                        side: handle.side,
+                        steps: handle.steps,
+                        cascade: selection.resize_floors_above,
+                    });
+                }
+            }
+            handle.steps = 0;
+            drag.handle = None;
+            if let Some((wall, range)) = trimmed {
+                for (mut handle, _) in &mut q_handles {
+                    if handle.wall == Some(wall) {
+                        handle.range = range.clone();
+                    }
+                }
+            }
+        }
+        return;
+    }
+
+    let hovered = spatial_query
+        .cast_ray(
+            ray.origin,
+            ray.direction,
+            1000.,
+            false,
+            SpatialQueryFilter::new().with_masks([Layer::Sensor]),
+        )
+        .map(|hit| hit.entity);
+    for (part_ent, parent, mut material, mut part) in &mut q_parts {
+        let highlighted = Some(part_ent) == hovered;
+        if highlighted != part.highlighted {
+            part.highlighted = highlighted;
+            *material = if highlighted {
+                ui_materials.ui_selected.clone()
+            } else {
+                part.material.clone()
+            };
+        }
+        if highlighted && mouse.just_pressed(MouseButton::Left) {
+            let Ok((handle, handle_gtr)) = q_handles.get(parent.get()) else {
+                continue;
+            };
+            let Ok((floor_gtr, _)) = q_floors.get(handle.floor) else {
+                continue;
+            };
+            let axis = if handle.side.x != 0 {
+                GizmoAxis::X
+            } else {
+                GizmoAxis::Z
+            };
+            if let Some(start) =
+                GizmoConstraint::Axis(axis).ray_cast(handle_gtr.translation(), &ray, floor_gtr)
+            {
+                drag.handle = Some(parent.get());
+                drag.start = start;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::Command;
+
+    use super::*;
+    use crate::{
+        ai::{
+            building::{SpawnBuilding, Stairs},
+            building_stairs::{ConnectorKind, SpawnConnector},
+        },
+        ui::basic_materials::TerrainMaterial,
+    };
+
+    /// A building of `floors` floors & a roof, over a square of `size` cells.
+    fn spawn_building(world: &mut World, floors: u32, size: f32) -> (Building, Entity) {
+        world.init_resource::<Assets<Mesh>>();
+        world.init_resource::<Assets<StandardMaterial>>();
+        world.init_resource::<Assets<TerrainMaterial>>();
+        world.init_resource::<BasicMaterials>();
+        let building = Building {
+            tile_size: 2.,
+            floor_height: 3.,
+            wall_thickness: 0.2,
+        };
+        SpawnBuilding {
+            building: building.clone(),
+            footprint: vec![
+                Vec2::ZERO,
+                Vec2::new(size, 0.),
+                Vec2::new(size, size),
+                Vec2::new(0., size),
+            ]
+            .into_iter()
+            .map(|v| v * building.tile_size)
+            .collect(),
+            floors,
+            transform: Transform::IDENTITY,
+        }
+        .apply(world);
+        let floor = world
+            .query::<(Entity, &Floor)>()
+            .iter(world)
+            .find(|(_, floor)| floor.level == 0)
+            .map(|(floor, _)| floor)
+            .unwrap();
+        (building, floor)
+    }
+
+    fn wall_at(world: &mut World, floor: Entity, outward: IVec2) -> (Entity, Wall) {
+        world
+            .query::<(Entity, &Wall, &Parent)>()
+            .iter(world)
+            .find(|(_, wall, parent)| parent.get() == floor && wall.outward == outward)
+            .map(|(ent, wall, _)| (ent, wall.clone()))
+            .unwrap()
+    }
+
+    #[test]
+    fn resize_keeps_doors_of_perpendicular_walls() {
+        let mut world = World::new();
+        let (building, floor) = spawn_building(&mut world, 1, 3.);
+        let (wall_ent, _) = wall_at(&mut world, floor, IVec2::NEG_Y);
+        let tile = world
+            .query::<(Entity, &WallTile, &Parent)>()
+            .iter(&world)
+            .find(|(_, tile, parent)| parent.get() == wall_ent && tile.index == 1)
+            .map(|(tile, ..)| tile)
+            .unwrap();
+        AddDoor {
+            width: 1.,
+            height: 2.,
+            center: 3.,
+        }
+        .apply(tile, &mut world);
+        // door position in floor space
+        let door_at = |world: &mut World| {
+            let doors: Vec<(Entity, Vec3)> = world
+                .query_filtered::<(&Parent, &Transform), With<Door>>()
+                .iter(world)
+                .map(|(parent, tr)| (parent.get(), tr.translation))
+                .collect();
+            assert_eq!(doors.len(), 1);
+            let (wall_ent, door) = doors[0];
+            let wall = world.get::<Wall>(wall_ent).unwrap();
+            assert_eq!(wall.outward, IVec2::NEG_Y);
+            (wall_ent, wall.transform(&building).transform_point(door))
+        };
+        let (_, before) = door_at(&mut world);
+
+        ResizeFloor {
+            side: IVec2::X,
+            steps: 1,
+            cascade: false,
+        }
+        .apply(floor, &mut world);
+
+        let (new_wall_ent, after) = door_at(&mut world);
+        assert_ne!(new_wall_ent, wall_ent);
+        assert!(before.distance(after) < 1e-4, "{before} -> {after}");
+    }
+
+    #[test]
+    fn resize_keeps_connectors_whole() {
+        let mut world = World::new();
+        let (_, ground) = spawn_building(&mut world, 2, 4.);
+        let upper = world
+            .query::<(Entity, &Floor)>()
+            .iter(&world)
+            .find(|(_, floor)| floor.level == 1)
+            .map(|(floor, _)| floor)
+            .unwrap();
+        SpawnConnector {
+            kind: ConnectorKind::Stairs,
+            cell: IVec2::new(1, 1),
+            direction: IVec2::Y,
+        }
+        .apply(ground, &mut world);
+        let stairs = world
+            .query_filtered::<&Room, With<Stairs>>()
+            .single(&world)
+            .cells
+            .clone();
+        assert_eq!(stairs, vec![IVec2::new(1, 1), IVec2::new(1, 2)]);
+        let cells = |world: &World, floor: Entity| world.get::<Floor>(floor).unwrap().cells.len();
+
+        // trims beside the stairs
+        ResizeFloor {
+            side: IVec2::X,
+            steps: -2,
+            cascade: true,
+        }
+        .apply(ground, &mut world);
+        assert_eq!((cells(&world, ground), cells(&world, upper)), (8, 8));
+
+        // trims the entry, or the exit on the floor above
+        for (floor, side) in [
+            (ground, IVec2::NEG_Y),
+            (upper, IVec2::Y),
+            (ground, IVec2::Y),
+        ] {
+            ResizeFloor {
+                side,
+                steps: -1,
+                cascade: true,
+            }
+            .apply(floor, &mut world);
+            assert_eq!((cells(&world, ground), cells(&world, upper)), (8, 8));
+        }
+    }
+}
@@ -0,0 +1,455 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use bevy::{ecs::system::SystemParam, prelude::*, utils::HashMap};
+
+use super::{
+    building::{Buildings, Door, DoorChangedEvent, Floor, Room, Stairs},
+    navmesh::RoomNavMesh,
+};
+
+pub struct BuildingPathPlugin;
+
+impl Plugin for BuildingPathPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                reroute_on_door_change,
+                update_building_routes,
+                draw_building_routes,
+            )
+                .chain(),
+        );
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+pub enum AgentKind {
+    /// Uses stairs & ramps.
+    #[default]
+    Person,
+    /// Only uses ramps.
+    Vehicle,
+}
+
+/// Size of an agent moving through buildings. Doors it does not fit through are ignored.
+#[derive(Component, Clone, Copy, Debug, Reflect)]
+pub struct BuildingAgent {
+    pub radius: f32,
+    pub height: f32,
+    pub kind: AgentKind,
+}
+
+impl Default for BuildingAgent {
+    fn default() -> Self {
+        Self {
+            radius: 0.3,
+            height: 1.8,
+            kind: AgentKind::Person,
+        }
+    }
+}
+
+impl BuildingAgent {
+    pub fn fits(&self, door: &DoorLink) -> bool {
+        door.opened
+            && door.width >= 2. * self.radius
+            && door.height >= self.height
+            && !(door.stairs && self.kind == AgentKind::Vehicle)
+    }
+}
+
+/// A node of the room graph: a room, or the outside of all buildings (`None`).
+///
+/// Only ground floor doors lead outside, see `RoomGraph::new`.
+pub type RoomNode = Option<Entity>;
+
+/// An edge of the room graph.
+#[derive(Clone, Debug)]
+pub struct DoorLink {
+    pub door: Entity,
+    pub inside: Entity,
+    pub outside: Option<Entity>,
+    /// World space center of the door.
+    pub position: Vec3,
+    pub width: f32,
+    pub height: f32,
+    pub opened: bool,
+    /// Leads into `Stairs`, which vehicles can't use.
+    pub stairs: bool,
+    /// Level of the floor holding `inside`.
+    pub level: u32,
+}
+
+impl DoorLink {
+    pub fn new(door: Entity, data: &Door, position: Vec3) -> Self {
+        Self {
+            door,
+            inside: data.inside,
+            outside: data.outside,
+            position,
+            width: data.width,
+            height: data.height,
+            opened: data.opened,
+            stairs: false,
+            level: 0,
+        }
+    }
+
+    fn rooms(&self) -> [RoomNode; 2] {
+        [Some(self.inside), self.outside]
+    }
+}
+
+/// The part of a route inside one room.
+#[derive(Clone, Debug)]
+pub struct RouteLeg {
+    pub room: RoomNode,
+    /// The door leaving the room, `None` for the last leg.
+    pub exit: Option<Entity>,
+    /// World space points, from the entry point to the exit door (or the goal).
+    /// Straight lines, unless refined with the room's nav mesh.
+    pub waypoints: Vec<Vec3>,
+}
+
+/// Graph of rooms (nodes) & doors (edges), for the first level of building pathfinding.
+#[derive(Clone, Default)]
+pub struct RoomGraph {
+    pub doors: Vec<DoorLink>,
+    by_room: HashMap<RoomNode, Vec<usize>>,
+}
+
+impl RoomGraph {
+    /// Doors leading outside above the ground floor are left out, as the outside node is shared
+    /// by all buildings at ground level.
+    pub fn new(doors: impl IntoIterator<Item = DoorLink>) -> Self {
+        let doors: Vec<_> = doors
+            .into_iter()
+            .filter(|door| door.outside.is_some() || door.level == 0)
+            .collect();
+        let mut by_room: HashMap<RoomNode, Vec<usize>> = HashMap::new();
+        for (i, door) in doors.iter().enumerate() {
+            for room in door.rooms() {
+                by_room.entry(room).or_default().push(i);
+            }
+        }
+        Self { doors, by_room }
+    }
+
+    /// A* over the doors the agent fits through, from `start` in room `start_room` to `goal` in `goal_room`.
+    ///
+    /// Returns one leg per room crossed, or `None` if the goal is unreachable.
+    pub fn find_route(
+        &self,
+        start_room: RoomNode,
+        start: Vec3,
+        goal_room: RoomNode,
+        goal: Vec3,
+        agent: &BuildingAgent,
+    ) -> Option<Vec<RouteLeg>> {
+        // search nodes: (door, side entered) pairs, then the start & goal
+        let start_node = 2 * self.doors.len();
+        let goal_node = start_node + 1;
+        let node_room = |node: usize| {
+            if node == start_node {
+                start_room
+            } else {
+                self.doors[node / 2].rooms()[node % 2]
+            }
+        };
+        let node_pos = |node: usize| match node {
+            n if n == start_node => start,
+            n if n == goal_node => goal,
+            n => self.doors[n / 2].position,
+        };
+
+        let mut came_from: HashMap<usize, usize> = HashMap::new();
+        let mut cost: HashMap<usize, f32> = HashMap::from([(start_node, 0.)]);
+        let mut open = BinaryHeap::from([OpenNode {
+            score: start.distance(goal),
+            node: start_node,
+        }]);
+        while let Some(OpenNode { node, .. }) = open.pop() {
+            if node == goal_node {
+                return Some(self.legs(&came_from, goal_node, start, goal, node_room));
+            }
+            let room = node_room(node);
+            let node_cost = cost[&node];
+            let mut neighbours: Vec<usize> = self
+                .by_room
+                .get(&room)
+                .into_iter()
+                .flatten()
+                .filter(|d| **d != node / 2 && agent.fits(&self.doors[**d]))
+                .map(|d| {
+                    // enter the door's other room
+                    let side = self.doors[*d].rooms().iter().position(|r| *r != room);
+                    2 * d + side.unwrap_or(0)
+                })
+                .collect();
+            if room == goal_room {
+                neighbours.push(goal_node);
+            }
+            for next in neighbours {
+                let next_cost = node_cost + node_pos(node).distance(node_pos(next));
+                if cost.get(&next).is_some_and(|c| *c <= next_cost) {
+                    continue;
+                }
+                cost.insert(next, next_cost);
+                came_from.insert(next, node);
+                open.push(OpenNode {
+                    score: next_cost + node_pos(next).distance(goal),
+                    node: next,
+                });
+            }
+        }
+        None
+    }
+
+    fn legs(
+        &self,
+        came_from: &HashMap<usize, usize>,
+        goal_node: usize,
+        start: Vec3,
+        goal: Vec3,
+        node_room: impl Fn(usize) -> RoomNode,
+    ) -> Vec<RouteLeg> {
+        let mut nodes = vec![goal_node];
+        while let Some(prev) = came_from.get(nodes.last().unwrap()) {
+            nodes.push(*prev);
+        }
+        nodes.reverse();
+
+        let mut legs = vec![];
+        let mut entry = start;
+        for pair in nodes.windows(2) {
+            let room = node_room(pair[0]);
+            let (exit, exit_pos) = if pair[1] == goal_node {
+                (None, goal)
+            } else {
+                let door = &self.doors[pair[1] / 2];
+                (Some(door.door), door.position)
+            };
+            legs.push(RouteLeg {
+                room,
+                exit,
+                waypoints: vec![entry, exit_pos],
+            });
+            entry = exit_pos;
+        }
+        legs
+    }
+}
+
+#[derive(PartialEq)]
+struct OpenNode {
+    score: f32,
+    node: usize,
+}
+
+impl Eq for OpenNode {}
+
+impl Ord for OpenNode {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // lowest score first
+        other.score.total_cmp(&self.score)
+    }
+}
+
+impl PartialOrd for OpenNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Builds `RoomGraph`s & locates rooms from inside systems.
+#[derive(SystemParam)]
+pub struct BuildingNav<'w, 's> {
+    q_doors: Query<'w, 's, (Entity, &'static Door, &'static GlobalTransform)>,
+    q_floors: Query<'w, 's, (&'static GlobalTransform, &'static Floor)>,
+    q_rooms: Query<'w, 's, &'static Parent, With<Room>>,
+    buildings: Res<'w, Buildings>,
+    q_nav_meshes: Query<'w, 's, (&'static RoomNavMesh, &'static Parent)>,
+    q_stairs: Query<'w, 's, (), With<Stairs>>,
+}
+
+impl<'w, 's> BuildingNav<'w, 's> {
+    /// Replaces the straight waypoints of a leg with a path through its room's nav mesh, if baked.
+    pub fn refine_leg(&self, leg: &mut RouteLeg) {
+        let Some((nav_mesh, parent)) = leg.room.and_then(|r| self.q_nav_meshes.get(r).ok()) else {
+            return;
+        };
+        let (Some(start), Some(goal)) = (leg.waypoints.first(), leg.waypoints.last()) else {
+            return;
+        };
+        let Ok((floor_gtr, _)) = self.q_floors.get(parent.get()) else {
+            return;
+        };
+        let to_floor = floor_gtr.affine().inverse();
+        let (start, goal) = (
+            to_floor.transform_point3(*start),
+            to_floor.transform_point3(*goal),
+        );
+        let Some(path) = nav_mesh.mesh.find_path(start.xz(), goal.xz()) else {
+            return;
+        };
+        let (first, last) = (leg.waypoints[0], *leg.waypoints.last().unwrap());
+        // keep the exact entry & exit points, which lie on walls, outside the nav mesh
+        leg.waypoints = std::iter::once(first)
+            .chain(
+                path.into_iter()
+                    .map(|p| floor_gtr.transform_point(Vec3::new(p.x, 0., p.y))),
+            )
+            .chain(std::iter::once(last))
+            .collect();
+        leg.waypoints.dedup_by(|a, b| a.distance(*b) < 0.01);
+    }
+
+    pub fn graph(&self) -> RoomGraph {
+        RoomGraph::new(self.q_doors.iter().map(|(entity, door, gtr)| {
+            DoorLink {
+                stairs: self.q_stairs.contains(door.inside)
+                    || door.outside.is_some_and(|o| self.q_stairs.contains(o)),
+                level: self
+                    .q_rooms
+                    .get(door.inside)
+                    .ok()
+                    .and_then(|floor| self.q_floors.get(floor.get()).ok())
+                    .map_or(0, |(_, floor)| floor.level),
+                ..DoorLink::new(entity, door, gtr.translation())
+            }
+        }))
+    }
+
+    /// The room containing a world space point, if any.
+    pub fn locate_room(&self, point: Vec3) -> RoomNode {
+        self.buildings.locate(point).and_then(|l| l.room)
+    }
+}
+
+/// Route of an agent through buildings, recomputed whenever `dirty` is set.
+#[derive(Component, Clone, Debug)]
+pub struct BuildingRoute {
+    /// World space destination.
+    pub goal: Vec3,
+    /// `None` if the goal is unreachable.
+    pub legs: Option<Vec<RouteLeg>>,
+    pub dirty: bool,
+}
+
+impl BuildingRoute {
+    pub fn to(goal: Vec3) -> Self {
+        Self {
+            goal,
+            legs: None,
+            dirty: true,
+        }
+    }
+
+    pub fn rooms(&self) -> impl Iterator<Item = RoomNode> + '_ {
+        self.legs.iter().flatten().map(|leg| leg.room)
+    }
+}
+
+/// Closed doors invalidate the routes through them, opened doors may shorten any route.
+fn reroute_on_door_change(
+    mut ev_door_changed: EventReader<DoorChangedEvent>,
+    mut q_routes: Query<&mut BuildingRoute>,
+) {
+    for ev in ev_door_changed.read() {
+        for mut route in &mut q_routes {
+            let uses_door = route
+                .legs
+                .iter()
+                .flatten()
+                .any(|leg| leg.exit == Some(ev.door));
+            if ev.opened || uses_door {
+                route.dirty = true;
+            }
+        }
+    }
+}
+
+fn update_building_routes(
+    nav: BuildingNav,
+    mut q_agents: Query<(&GlobalTransform, &BuildingAgent, &mut BuildingRoute)>,
+) {
+    let mut graph = None;
+    for (agent_gtr, agent, mut route) in &mut q_agents {
+        if !route.dirty {
+            continue;
+        }
+        let graph = graph.get_or_insert_with(|| nav.graph());
+        let start = agent_gtr.translation();
+        let start_room = nav.locate_room(start);
+        let goal_room = nav.locate_room(route.goal);
+        route.legs = graph.find_route(start_room, start, goal_room, route.goal, agent);
+        for leg in route.legs.iter_mut().flatten() {
+            nav.refine_leg(leg);
+        }
+        route.dirty = false;
+    }
+}
+
+fn draw_building_routes(mut gizmos: Gizmos, q_routes: Query<&BuildingRoute>) {
+    for route in &q_routes {
+        for leg in route.legs.iter().flatten() {
+            gizmos.linestrip(leg.waypoints.iter().copied(), Color::YELLOW);
+            if leg.exit.is_some() {
+                if let Some(exit) = leg.waypoints.last() {
+                    gizmos.sphere(*exit, Quat::IDENTITY, 0.2, Color::YELLOW);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn door(door: u32, inside: u32, outside: Option<u32>, position: Vec3, level: u32) -> DoorLink {
+        DoorLink {
+            door: Entity::from_raw(door),
+            inside: Entity::from_raw(inside),
+            outside: outside.map(Entity::from_raw),
+            position,
+            width: 1.,
+            height: 2.,
+            opened: true,
+            stairs: false,
+            level,
+        }
+    }
+
+    #[test]
+    fn upper_floor_exits_through_the_stairs() {
+        let (ground, upper, stairs) = (1, 2, 3);
+        let graph = RoomGraph::new([
+            door(10, ground, None, Vec3::new(0., 0.5, 0.), 0),
+            // a balcony door, next to the agent
+            door(11, upper, None, Vec3::new(10., 3.5, 0.), 1),
+            door(12, ground, Some(stairs), Vec3::new(5., 0.5, 5.), 0),
+            door(13, upper, Some(stairs), Vec3::new(5., 3.5, 9.), 1),
+        ]);
+        let legs = graph
+            .find_route(
+                Some(Entity::from_raw(upper)),
+                Vec3::new(9., 3.5, 0.),
+                None,
+                Vec3::new(-1., 0., 0.),
+                &BuildingAgent::default(),
+            )
+            .unwrap();
+        let rooms: Vec<RoomNode> = legs.iter().map(|leg| leg.room).collect();
+        let exits: Vec<Option<Entity>> = legs.iter().map(|leg| leg.exit).collect();
+        assert_eq!(
+            rooms,
+            [Some(upper), Some(stairs), Some(ground), None].map(|r| r.map(Entity::from_raw))
+        );
+        assert_eq!(
+            exits,
+            [Some(13), Some(12), Some(10), None].map(|d| d.map(Entity::from_raw))
+        );
+    }
+}
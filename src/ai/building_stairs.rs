@@ -0,0 +1,360 @@
+use bevy::{ecs::system::EntityCommand, prelude::*};
+use bevy_xpbd_3d::prelude::*;
+
+use crate::{
+    camera::MainCamera,
+    ui::{
+        basic_materials::BasicMaterials,
+        selection::{Layer, Selectable},
+        side_panel::{SidePanel, UiMode},
+    },
+};
+
+use super::building::{
+    is_connector, Building, Door, Floor, FloorTile, Ramp, Roof, Room, Stairs, SLAB_THICKNESS,
+};
+
+pub struct BuildingStairsPlugin;
+
+impl Plugin for BuildingStairsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, add_connector);
+    }
+}
+
+/// Rise over run of stairs.
+const STAIRS_SLOPE: f32 = 0.75;
+/// Rise over run of ramps.
+const RAMP_SLOPE: f32 = 0.4;
+/// Maximum height of a stair step.
+const STEP_RISE: f32 = 0.2;
+const CONNECTOR_DOOR_HEIGHT: f32 = 2.2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectorKind {
+    Stairs,
+    Ramp,
+}
+
+impl ConnectorKind {
+    /// Number of cells needed to climb one floor.
+    pub fn run_cells(&self, building: &Building) -> i32 {
+        let slope = match self {
+            ConnectorKind::Stairs => STAIRS_SLOPE,
+            ConnectorKind::Ramp => RAMP_SLOPE,
+        };
+        (building.floor_height / (slope * building.tile_size)).ceil() as i32
+    }
+}
+
+/// Geometry of `Stairs` or a `Ramp`. Not an obstacle for doors & nav meshes.
+#[derive(Component)]
+pub struct ConnectorPart;
+
+/// Cells covered by a connector climbing from `cell` in `direction`.
+pub fn connector_cells(cell: IVec2, direction: IVec2, run_cells: i32) -> Vec<IVec2> {
+    (0..run_cells).map(|i| cell + direction * i).collect()
+}
+
+/// Cells of a `Floor` its stairs & ramps need: their own & entry cells,
+/// and the openings & exit cells of those climbing from the floor below.
+pub fn connector_footprint(world: &World, floor: Entity) -> Vec<IVec2> {
+    let Some(level) = world.get::<Floor>(floor).map(|f| f.level) else {
+        return vec![];
+    };
+    let Some(building) = world.get::<Parent>(floor).map(|p| p.get()) else {
+        return vec![];
+    };
+    let mut footprint = vec![];
+    for floor_ent in children(world, building) {
+        let Some(from) = world.get::<Floor>(floor_ent).map(|f| f.level) else {
+            continue;
+        };
+        if from != level && from + 1 != level {
+            continue;
+        }
+        for room_ent in children(world, floor_ent) {
+            if !is_connector(world, room_ent) {
+                continue;
+            }
+            let (Some(room), Some(transform)) = (
+                world.get::<Room>(room_ent),
+                world.get::<Transform>(room_ent),
+            ) else {
+                continue;
+            };
+            let (Some(first), Some(last)) = (room.cells.first(), room.cells.last()) else {
+                continue;
+            };
+            // the room's +Z climbs
+            let dir = (transform.rotation * Vec3::Z).round();
+            let direction = IVec2::new(dir.x as i32, dir.z as i32);
+            footprint.extend(room.cells.iter().copied());
+            footprint.push(if from == level {
+                *first - direction
+            } else {
+                *last + direction
+            });
+        }
+    }
+    footprint
+}
+
+/// Spawns stairs or a ramp on a `Floor`, climbing to the floor above.
+///
+/// The connector is a special room with a door to the room it starts from, on this floor,
+/// & a door to the room it leads to, on the floor above. The floor above gets an opening over it.
+pub struct SpawnConnector {
+    pub kind: ConnectorKind,
+    pub cell: IVec2,
+    pub direction: IVec2,
+}
+
+impl EntityCommand for SpawnConnector {
+    fn apply(self, id: Entity, world: &mut World) {
+        let Some(floor) = world.get::<Floor>(id).cloned() else {
+            return;
+        };
+        let Some(building_ent) = world.get::<Parent>(id).map(|p| p.get()) else {
+            return;
+        };
+        let Some(building) = world.get::<Building>(building_ent).cloned() else {
+            return;
+        };
+        let Some((upper_ent, mut upper)) = children(world, building_ent)
+            .into_iter()
+            .filter(|e| world.get::<Roof>(*e).is_none())
+            .find_map(|e| {
+                let upper = world.get::<Floor>(e)?;
+                (upper.level == floor.level + 1).then(|| (e, upper.clone()))
+            })
+        else {
+            return;
+        };
+
+        let run_cells = self.kind.run_cells(&building);
+        let cells = connector_cells(self.cell, self.direction, run_cells);
+        let entry = self.cell - self.direction;
+        let exit = self.cell + self.direction * run_cells;
+        let lower_rooms: Vec<Entity> = children(world, id)
+            .into_iter()
+            .filter(|e| world.get::<Room>(*e).is_some())
+            .collect();
+        let taken = lower_rooms.iter().any(|r| {
+            is_connector(world, *r)
+                && world
+                    .get::<Room>(*r)
+                    .is_some_and(|room| cells.iter().any(|c| room.cells.contains(c)))
+        });
+        if taken
+            || !cells
+                .iter()
+                .all(|c| floor.contains(*c) && upper.contains(*c))
+            || !floor.contains(entry)
+            || !upper.contains(exit)
+            || upper.openings.contains(&exit)
+        {
+            return;
+        }
+
+        // the connector's cells leave the rooms below
+        for room_ent in &lower_rooms {
+            if is_connector(world, *room_ent) {
+                continue;
+            }
+            if let Some(mut room) = world.get_mut::<Room>(*room_ent) {
+                if room.cells.iter().any(|c| cells.contains(c)) {
+                    room.cells.retain(|c| !cells.contains(c));
+                }
+            }
+        }
+        let room_at = |world: &World, floor: Entity, cell: IVec2| {
+            children(world, floor).into_iter().find(|e| {
+                !is_connector(world, *e)
+                    && world
+                        .get::<Room>(*e)
+                        .is_some_and(|room| room.cells.contains(&cell))
+            })
+        };
+        let entry_room = room_at(world, id, entry);
+        let exit_room = room_at(world, upper_ent, exit);
+
+        // open the floor above
+        for tile in children(world, upper_ent) {
+            if world
+                .get::<FloorTile>(tile)
+                .is_some_and(|t| cells.contains(&t.cell))
+            {
+                world.entity_mut(tile).despawn_recursive();
+            }
+        }
+        upper.openings.extend(cells.iter().copied());
+        world.entity_mut(upper_ent).insert(upper);
+
+        let dir = Vec3::new(self.direction.x as f32, 0., self.direction.y as f32);
+        let start = building.cell_center(self.cell) - dir * building.tile_size / 2.;
+        let run = run_cells as f32 * building.tile_size;
+        let rise = building.floor_height;
+        let width = building.tile_size * 0.9;
+        let facing = Quat::from_rotation_y(dir.x.atan2(dir.z));
+
+        let room = world
+            .spawn((
+                Room {
+                    cells: cells.clone(),
+                },
+                SpatialBundle::from_transform(
+                    Transform::from_translation(start).with_rotation(facing),
+                ),
+            ))
+            .set_parent(id)
+            .id();
+        let name = match self.kind {
+            ConnectorKind::Stairs => {
+                world.entity_mut(room).insert(Stairs);
+                "Stairs"
+            }
+            ConnectorKind::Ramp => {
+                world.entity_mut(room).insert(Ramp);
+                "Ramp"
+            }
+        };
+        world
+            .entity_mut(room)
+            .insert(Name::new(format!("{name} ({room:?})")));
+
+        // parts in room space: +Z climbs, starting at the bottom edge
+        let parts: Vec<(Vec3, Transform)> = match self.kind {
+            ConnectorKind::Stairs => {
+                let steps = (rise / STEP_RISE).ceil() as u32;
+                let tread = run / steps as f32;
+                (0..steps)
+                    .map(|i| {
+                        let height = rise * (i + 1) as f32 / steps as f32;
+                        (
+                            Vec3::new(width, height, tread),
+                            Transform::from_xyz(0., height / 2., tread * (i as f32 + 0.5)),
+                        )
+                    })
+                    .collect()
+            }
+            ConnectorKind::Ramp => {
+                let slope = Quat::from_rotation_x(-rise.atan2(run));
+                let center =
+                    Vec3::new(0., rise / 2., run / 2.) - slope * Vec3::Y * SLAB_THICKNESS / 2.;
+                vec![(
+                    Vec3::new(width, SLAB_THICKNESS, Vec2::new(run, rise).length()),
+                    Transform::from_translation(center).with_rotation(slope),
+                )]
+            }
+        };
+        let material = world.resource::<BasicMaterials>().building_floor.clone();
+        for (size, transform) in parts {
+            let mesh = world
+                .resource_mut::<Assets<Mesh>>()
+                .add(Mesh::from(shape::Box::new(size.x, size.y, size.z)));
+            let part = world
+                .spawn((
+                    ConnectorPart,
+                    PbrBundle {
+                        transform,
+                        mesh,
+                        material: material.clone(),
+                        ..default()
+                    },
+                    RigidBody::Static,
+                    Collider::cuboid(size.x, size.y, size.z),
+                    CollisionLayers::new([Layer::Object], [Layer::Object]),
+                ))
+                .set_parent(room)
+                .id();
+            world
+                .entity_mut(part)
+                .insert(Selectable::new(room, Some(part)));
+        }
+
+        for (outside, position) in [
+            (entry_room, Vec3::Y * CONNECTOR_DOOR_HEIGHT / 2.),
+            (
+                exit_room,
+                Vec3::new(0., rise + CONNECTOR_DOOR_HEIGHT / 2., run),
+            ),
+        ] {
+            let door = world
+                .spawn((
+                    Door {
+                        outside,
+                        inside: room,
+                        width,
+                        height: CONNECTOR_DOOR_HEIGHT,
+                        opened: true,
+                    },
+                    SpatialBundle::from_transform(Transform::from_translation(position)),
+                ))
+                .set_parent(room)
+                .id();
+            world
+                .entity_mut(door)
+                .insert(Name::new(format!("Door ({door:?})")));
+        }
+    }
+}
+
+fn children(world: &World, entity: Entity) -> Vec<Entity> {
+    world
+        .get::<Children>(entity)
+        .map(|c| c.to_vec())
+        .unwrap_or_default()
+}
+
+fn add_connector(
+    mouse: Res<Input<MouseButton>>,
+    spatial_query: SpatialQuery,
+    panel: Res<SidePanel>,
+    q_camera: Query<&MainCamera>,
+    q_floor_tile: Query<(&Parent, &FloorTile)>,
+    q_gtr: Query<&GlobalTransform>,
+    mut cmd: Commands,
+) {
+    let kind = match panel.mode {
+        UiMode::AddStairs => ConnectorKind::Stairs,
+        UiMode::AddRamp => ConnectorKind::Ramp,
+        _ => return,
+    };
+    if panel.mouse_over || !mouse.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let Ok(Some(ray)) = q_camera.get_single().map(|c| c.mouse_ray) else {
+        return;
+    };
+    let Some(hit) = spatial_query.cast_ray(
+        ray.origin,
+        ray.direction,
+        1000.,
+        false,
+        SpatialQueryFilter::new().with_masks([Layer::Object]),
+    ) else {
+        return;
+    };
+    let Ok((parent, tile)) = q_floor_tile.get(hit.entity) else {
+        return;
+    };
+    let Ok(floor_gtr) = q_gtr.get(parent.get()) else {
+        return;
+    };
+    // climb away from the camera, along the closest grid axis
+    let look = floor_gtr
+        .affine()
+        .inverse()
+        .transform_vector3(ray.direction);
+    let direction = if look.x.abs() > look.z.abs() {
+        IVec2::new(look.x.signum() as i32, 0)
+    } else {
+        IVec2::new(0, look.z.signum() as i32)
+    };
+    cmd.entity(parent.get()).add(SpawnConnector {
+        kind,
+        cell: tile.cell,
+        direction,
+    });
+}
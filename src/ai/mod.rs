@@ -1,3 +1,7 @@
 pub mod building;
+pub mod building_edit;
+pub mod building_path;
+pub mod building_stairs;
+pub mod navmesh;
 pub mod swarm;
 pub mod terrain;
@@ -0,0 +1,609 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use bevy::{
+    ecs::system::EntityCommand,
+    prelude::*,
+    render::primitives::Aabb,
+    utils::{HashMap, HashSet},
+};
+
+use crate::ui::side_panel::SidePanel;
+
+use super::{
+    building::{Building, Floor, FloorTile, Room, WallTile},
+    building_path::BuildingAgent,
+    building_stairs::ConnectorPart,
+};
+
+pub struct NavMeshPlugin;
+
+impl Plugin for NavMeshPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                mark_dirty_nav_meshes,
+                bake_dirty_nav_meshes,
+                draw_nav_meshes,
+            )
+                .chain(),
+        );
+    }
+}
+
+/// Nav mesh cells per floor tile, along each axis.
+pub const NAV_SUBDIVISIONS: i32 = 8;
+
+/// A portal between two adjacent nav mesh polygons.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NavLink {
+    pub a: usize,
+    pub b: usize,
+    /// The shared edge.
+    pub portal: (Vec2, Vec2),
+}
+
+/// Walkable area as convex polygons, in floor space XZ.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NavMesh {
+    /// Counter-clockwise (looking down) convex polygons.
+    pub polygons: Vec<Vec<Vec2>>,
+    pub links: Vec<NavLink>,
+}
+
+impl NavMesh {
+    pub fn polygon_at(&self, point: Vec2) -> Option<usize> {
+        self.polygons.iter().position(|poly| {
+            poly.iter().enumerate().all(|(i, a)| {
+                let b = poly[(i + 1) % poly.len()];
+                (b - *a).perp_dot(point - *a) <= 1e-4
+            })
+        })
+    }
+
+    /// The polygon closest to `point` & the closest point inside it.
+    pub fn closest_point(&self, point: Vec2) -> Option<(usize, Vec2)> {
+        if let Some(poly) = self.polygon_at(point) {
+            return Some((poly, point));
+        }
+        self.polygons
+            .iter()
+            .enumerate()
+            .map(|(i, poly)| {
+                let closest = (0..poly.len())
+                    .map(|j| closest_on_segment(point, poly[j], poly[(j + 1) % poly.len()]))
+                    .min_by(|a, b| a.distance(point).total_cmp(&b.distance(point)))
+                    .unwrap_or(point);
+                (i, closest)
+            })
+            .min_by(|(_, a), (_, b)| a.distance(point).total_cmp(&b.distance(point)))
+    }
+
+    /// Shortest path between two points, both clamped onto the mesh.
+    ///
+    /// A* over the polygons, then string pulled through the portals.
+    pub fn find_path(&self, start: Vec2, goal: Vec2) -> Option<Vec<Vec2>> {
+        let (start_poly, start) = self.closest_point(start)?;
+        let (goal_poly, goal) = self.closest_point(goal)?;
+
+        let mut links_of: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (i, link) in self.links.iter().enumerate() {
+            links_of.entry(link.a).or_default().push(i);
+            links_of.entry(link.b).or_default().push(i);
+        }
+        let portal_mid = |link: usize| {
+            let (l, r) = self.links[link].portal;
+            (l + r) / 2.
+        };
+
+        // nodes are polygons, entered at the middle of a portal
+        let mut came_from: HashMap<usize, (usize, usize)> = HashMap::new();
+        let mut cost: HashMap<usize, f32> = HashMap::from([(start_poly, 0.)]);
+        let mut entry: HashMap<usize, Vec2> = HashMap::from([(start_poly, start)]);
+        let mut open = BinaryHeap::from([OpenPoly {
+            score: start.distance(goal),
+            poly: start_poly,
+        }]);
+        let mut visited = HashSet::new();
+        while let Some(OpenPoly { poly, .. }) = open.pop() {
+            if poly == goal_poly {
+                break;
+            }
+            if !visited.insert(poly) {
+                continue;
+            }
+            for link in links_of.get(&poly).into_iter().flatten() {
+                let l = &self.links[*link];
+                let next = if l.a == poly { l.b } else { l.a };
+                let pos = portal_mid(*link);
+                let next_cost = cost[&poly] + entry[&poly].distance(pos);
+                if cost.get(&next).is_some_and(|c| *c <= next_cost) {
+                    continue;
+                }
+                cost.insert(next, next_cost);
+                entry.insert(next, pos);
+                came_from.insert(next, (poly, *link));
+                open.push(OpenPoly {
+                    score: next_cost + pos.distance(goal),
+                    poly: next,
+                });
+            }
+        }
+        if !cost.contains_key(&goal_poly) {
+            return None;
+        }
+
+        // portals in travel order, as (left, right) seen from the previous polygon
+        let mut portals = vec![];
+        let mut poly = goal_poly;
+        while let Some((prev, link)) = came_from.get(&poly) {
+            let (p0, p1) = self.links[*link].portal;
+            let dir = polygon_center(&self.polygons[poly]) - polygon_center(&self.polygons[*prev]);
+            portals.push(if dir.perp_dot(p0 - p1) > 0. {
+                (p0, p1)
+            } else {
+                (p1, p0)
+            });
+            poly = *prev;
+        }
+        portals.reverse();
+        Some(string_pull(start, goal, &portals))
+    }
+}
+
+#[derive(PartialEq)]
+struct OpenPoly {
+    score: f32,
+    poly: usize,
+}
+
+impl Eq for OpenPoly {}
+
+impl Ord for OpenPoly {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // lowest score first
+        other.score.total_cmp(&self.score)
+    }
+}
+
+impl PartialOrd for OpenPoly {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn polygon_center(poly: &[Vec2]) -> Vec2 {
+    poly.iter().sum::<Vec2>() / poly.len().max(1) as f32
+}
+
+fn closest_on_segment(p: Vec2, a: Vec2, b: Vec2) -> Vec2 {
+    let ab = b - a;
+    let t = ((p - a).dot(ab) / ab.length_squared().max(1e-6)).clamp(0., 1.);
+    a + t * ab
+}
+
+/// The "simple stupid funnel algorithm". Portals are (left, right) pairs.
+fn string_pull(start: Vec2, goal: Vec2, portals: &[(Vec2, Vec2)]) -> Vec<Vec2> {
+    let portals: Vec<(Vec2, Vec2)> = portals
+        .iter()
+        .copied()
+        .chain(std::iter::once((goal, goal)))
+        .collect();
+    // > 0 when c is left of a->b
+    let side = |a: Vec2, b: Vec2, c: Vec2| (b - a).perp_dot(c - a);
+
+    let mut path = vec![start];
+    let (mut apex, mut left, mut right) = (start, start, start);
+    let (mut left_idx, mut right_idx) = (0, 0);
+    let mut i = 0;
+    while i < portals.len() {
+        let (l, r) = portals[i];
+        // tighten the right side
+        if side(apex, right, r) >= 0. {
+            if apex == right || side(apex, left, r) < 0. {
+                right = r;
+                right_idx = i;
+            } else {
+                path.push(left);
+                apex = left;
+                (left, right) = (apex, apex);
+                right_idx = left_idx;
+                i = left_idx + 1;
+                continue;
+            }
+        }
+        // tighten the left side
+        if side(apex, left, l) <= 0. {
+            if apex == left || side(apex, right, l) > 0. {
+                left = l;
+                left_idx = i;
+            } else {
+                path.push(right);
+                apex = right;
+                (left, right) = (apex, apex);
+                left_idx = right_idx;
+                i = right_idx + 1;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    if path.last() != Some(&goal) {
+        path.push(goal);
+    }
+    path
+}
+
+/// Rasterizes `cells` at `NAV_SUBDIVISIONS` per tile, removes the `obstacles` inflated by the agent's radius,
+/// then merges what is left into rectangles.
+///
+/// Everything is in floor space XZ.
+pub fn bake_nav_mesh(
+    cells: &[IVec2],
+    tile_size: f32,
+    obstacles: &[Rect],
+    agent: &BuildingAgent,
+) -> NavMesh {
+    let (Some(min), Some(max)) = (
+        cells.iter().copied().reduce(IVec2::min),
+        cells.iter().copied().reduce(IVec2::max),
+    ) else {
+        return NavMesh::default();
+    };
+    let cell_set: HashSet<IVec2> = cells.iter().copied().collect();
+    let step = tile_size / NAV_SUBDIVISIONS as f32;
+    let origin = min * NAV_SUBDIVISIONS;
+    let size = (max - min + IVec2::ONE) * NAV_SUBDIVISIONS;
+    let inflated: Vec<Rect> = obstacles
+        .iter()
+        .map(|r| Rect::from_corners(r.min - agent.radius, r.max + agent.radius))
+        .collect();
+
+    let idx = |p: IVec2| (p.y * size.x + p.x) as usize;
+    let mut walkable = vec![false; (size.x * size.y) as usize];
+    for y in 0..size.y {
+        for x in 0..size.x {
+            let sub = origin + IVec2::new(x, y);
+            let center = (sub.as_vec2() + 0.5) * step;
+            walkable[idx(IVec2::new(x, y))] = cell_set
+                .contains(&sub.div_euclid(IVec2::splat(NAV_SUBDIVISIONS)))
+                && !inflated.iter().any(|r| r.contains(center));
+        }
+    }
+
+    // greedy maximal rectangles
+    let mut rects: Vec<(IVec2, IVec2)> = vec![];
+    let mut used = vec![false; walkable.len()];
+    let free = |p: IVec2, used: &[bool]| walkable[idx(p)] && !used[idx(p)];
+    for y in 0..size.y {
+        for x in 0..size.x {
+            if !free(IVec2::new(x, y), &used) {
+                continue;
+            }
+            let mut w = 1;
+            while x + w < size.x && free(IVec2::new(x + w, y), &used) {
+                w += 1;
+            }
+            let mut h = 1;
+            while y + h < size.y && (x..x + w).all(|xx| free(IVec2::new(xx, y + h), &used)) {
+                h += 1;
+            }
+            for yy in y..y + h {
+                for xx in x..x + w {
+                    used[idx(IVec2::new(xx, yy))] = true;
+                }
+            }
+            rects.push((IVec2::new(x, y), IVec2::new(x + w, y + h)));
+        }
+    }
+
+    let to_floor = |p: IVec2| (origin + p).as_vec2() * step;
+    let polygons = rects
+        .iter()
+        .map(|(a, b)| {
+            // counter-clockwise seen from above, with +Z down the screen
+            vec![
+                to_floor(*a),
+                to_floor(IVec2::new(a.x, b.y)),
+                to_floor(*b),
+                to_floor(IVec2::new(b.x, a.y)),
+            ]
+        })
+        .map(|poly: Vec<Vec2>| {
+            // keep a consistent winding for `polygon_at`
+            let area: f32 = (0..poly.len())
+                .map(|i| poly[i].perp_dot(poly[(i + 1) % poly.len()]))
+                .sum();
+            if area > 0. {
+                poly.into_iter().rev().collect()
+            } else {
+                poly
+            }
+        })
+        .collect();
+
+    let mut links = vec![];
+    for (i, (a0, a1)) in rects.iter().enumerate() {
+        for (j, (b0, b1)) in rects.iter().enumerate().skip(i + 1) {
+            let portal = if a1.x == b0.x || b1.x == a0.x {
+                let x = if a1.x == b0.x { a1.x } else { a0.x };
+                let (y0, y1) = (a0.y.max(b0.y), a1.y.min(b1.y));
+                (y1 > y0).then(|| (IVec2::new(x, y0), IVec2::new(x, y1)))
+            } else if a1.y == b0.y || b1.y == a0.y {
+                let y = if a1.y == b0.y { a1.y } else { a0.y };
+                let (x0, x1) = (a0.x.max(b0.x), a1.x.min(b1.x));
+                (x1 > x0).then(|| (IVec2::new(x0, y), IVec2::new(x1, y)))
+            } else {
+                None
+            };
+            if let Some((p0, p1)) = portal {
+                links.push(NavLink {
+                    a: i,
+                    b: j,
+                    portal: (to_floor(p0), to_floor(p1)),
+                });
+            }
+        }
+    }
+
+    NavMesh { polygons, links }
+}
+
+/// Nav mesh of a `Room`, in the space of its `Floor`.
+#[derive(Component, Clone, Debug)]
+pub struct RoomNavMesh {
+    pub mesh: NavMesh,
+    pub agent: BuildingAgent,
+}
+
+/// Marks a `Room` whose nav mesh needs rebaking.
+#[derive(Component)]
+pub struct NavMeshDirty;
+
+/// Bakes the nav mesh of a `Room` from its floor tiles, the wall tiles of its floor & the furniture inside it.
+///
+/// Obstacles are the `Aabb`s of meshes overlapping the agent's height above the floor.
+pub struct BakeRoomNavMesh {
+    pub agent: BuildingAgent,
+}
+
+impl EntityCommand for BakeRoomNavMesh {
+    fn apply(self, id: Entity, world: &mut World) {
+        let Some(room) = world.get::<Room>(id).cloned() else {
+            return;
+        };
+        let Some(floor_ent) = world.get::<Parent>(id).map(|p| p.get()) else {
+            return;
+        };
+        let Some(building) = world
+            .get::<Parent>(floor_ent)
+            .and_then(|p| world.get::<Building>(p.get()))
+            .cloned()
+        else {
+            return;
+        };
+        let Some(to_floor) = world
+            .get::<GlobalTransform>(floor_ent)
+            .map(|gtr| gtr.affine().inverse())
+        else {
+            return;
+        };
+
+        let floor_children = world
+            .get::<Children>(floor_ent)
+            .map(|c| c.to_vec())
+            .unwrap_or_default();
+        let tiles: HashSet<IVec2> = floor_children
+            .iter()
+            .filter_map(|c| world.get::<FloorTile>(*c).map(|t| t.cell))
+            .collect();
+        let cells: Vec<IVec2> = room
+            .cells
+            .iter()
+            .filter(|c| tiles.contains(*c))
+            .copied()
+            .collect();
+
+        // wall tiles & the room's furniture, but not other rooms' furniture
+        let mut obstacle_ents = vec![];
+        for child in &floor_children {
+            if world.get::<Room>(*child).is_some() && *child != id {
+                continue;
+            }
+            collect_descendants(world, *child, &mut obstacle_ents);
+        }
+        let obstacles: Vec<Rect> = obstacle_ents
+            .into_iter()
+            .filter(|e| {
+                world.get::<FloorTile>(*e).is_none() && world.get::<ConnectorPart>(*e).is_none()
+            })
+            .filter_map(|e| {
+                let aabb = world.get::<Aabb>(e)?;
+                let gtr = world.get::<GlobalTransform>(e)?;
+                let (min, max) = (aabb.min(), aabb.max());
+                let (lo, hi) = (0..8)
+                    .map(|i| {
+                        let corner = Vec3::new(
+                            if i & 1 == 0 { min.x } else { max.x },
+                            if i & 2 == 0 { min.y } else { max.y },
+                            if i & 4 == 0 { min.z } else { max.z },
+                        );
+                        to_floor.transform_point3(gtr.transform_point(corner))
+                    })
+                    .fold((Vec3::MAX, Vec3::MIN), |(lo, hi), p| (lo.min(p), hi.max(p)));
+                (hi.y > 0.05 && lo.y < self.agent.height).then(|| Rect::new(lo.x, lo.z, hi.x, hi.z))
+            })
+            .collect();
+
+        let mesh = bake_nav_mesh(&cells, building.tile_size, &obstacles, &self.agent);
+        world
+            .entity_mut(id)
+            .insert(RoomNavMesh {
+                mesh,
+                agent: self.agent,
+            })
+            .remove::<NavMeshDirty>();
+    }
+}
+
+fn collect_descendants(world: &World, entity: Entity, out: &mut Vec<Entity>) {
+    out.push(entity);
+    for child in world.get::<Children>(entity).into_iter().flatten() {
+        collect_descendants(world, *child, out);
+    }
+}
+
+fn mark_dirty_nav_meshes(
+    q_new_rooms: Query<Entity, (With<Room>, Without<RoomNavMesh>, Without<NavMeshDirty>)>,
+    q_changed_rooms: Query<Entity, Changed<Room>>,
+    q_changed_walls: Query<
+        (Entity, &Parent),
+        (
+            With<WallTile>,
+            Or<(Added<WallTile>, Changed<GlobalTransform>)>,
+        ),
+    >,
+    mut removed_walls: RemovedComponents<WallTile>,
+    mut wall_floors: Local<HashMap<Entity, Entity>>,
+    q_moved: Query<Entity, (With<Aabb>, Changed<GlobalTransform>, Without<WallTile>)>,
+    q_floors: Query<&Children, With<Floor>>,
+    q_rooms: Query<(), With<Room>>,
+    q_parent: Query<&Parent>,
+    mut cmd: Commands,
+) {
+    let mut dirty: HashSet<Entity> = q_new_rooms.iter().chain(&q_changed_rooms).collect();
+    let mut dirty_floors = HashSet::new();
+    // wall tile -> wall -> floor, remembered for when the tile is removed
+    for (tile, wall) in &q_changed_walls {
+        if let Ok(floor) = q_parent.get(wall.get()) {
+            dirty_floors.insert(floor.get());
+            wall_floors.insert(tile, floor.get());
+        }
+    }
+    for tile in removed_walls.read() {
+        if let Some(floor) = wall_floors.remove(&tile) {
+            dirty_floors.insert(floor);
+        }
+    }
+    for floor in dirty_floors {
+        if let Ok(children) = q_floors.get(floor) {
+            dirty.extend(children.iter().filter(|c| q_rooms.contains(**c)));
+        }
+    }
+    for moved in &q_moved {
+        if let Some(room) = q_parent
+            .iter_ancestors(moved)
+            .find(|e| q_rooms.contains(*e))
+        {
+            dirty.insert(room);
+        }
+    }
+    for room in dirty {
+        cmd.entity(room).insert(NavMeshDirty);
+    }
+}
+
+fn bake_dirty_nav_meshes(
+    q_dirty: Query<(Entity, Option<&RoomNavMesh>), With<NavMeshDirty>>,
+    mut cmd: Commands,
+) {
+    for (room, nav_mesh) in &q_dirty {
+        let agent = nav_mesh.map_or_else(BuildingAgent::default, |n| n.agent);
+        cmd.entity(room).add(BakeRoomNavMesh { agent });
+    }
+}
+
+fn draw_nav_meshes(
+    panel: Res<SidePanel>,
+    mut gizmos: Gizmos,
+    q_nav_meshes: Query<(&RoomNavMesh, &Parent)>,
+    q_floors: Query<&GlobalTransform, With<Floor>>,
+) {
+    if !panel.nav_mesh_debug_enabled {
+        return;
+    }
+    for (nav_mesh, parent) in &q_nav_meshes {
+        let Ok(floor_gtr) = q_floors.get(parent.get()) else {
+            continue;
+        };
+        let to_world = |p: Vec2| floor_gtr.transform_point(Vec3::new(p.x, 0.05, p.y));
+        for poly in &nav_mesh.mesh.polygons {
+            gizmos.linestrip(
+                poly.iter().chain(poly.first()).map(|p| to_world(*p)),
+                Color::CYAN,
+            );
+        }
+        for link in &nav_mesh.mesh.links {
+            gizmos.line(
+                to_world(link.portal.0),
+                to_world(link.portal.1),
+                Color::LIME_GREEN,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 3x3 tiles room with a pillar in the middle.
+    fn pillar_room() -> NavMesh {
+        let cells: Vec<IVec2> = (0..3)
+            .flat_map(|y| (0..3).map(move |x| IVec2::new(x, y)))
+            .collect();
+        let pillar = Rect::new(2.5, 2.5, 3.5, 3.5);
+        bake_nav_mesh(&cells, 2., &[pillar], &BuildingAgent::default())
+    }
+
+    fn area(poly: &[Vec2]) -> f32 {
+        (0..poly.len())
+            .map(|i| poly[i].perp_dot(poly[(i + 1) % poly.len()]))
+            .sum::<f32>()
+            .abs()
+            / 2.
+    }
+
+    #[test]
+    fn bake_leaves_out_the_inflated_obstacle() {
+        let mesh = pillar_room();
+        assert!(!mesh.polygons.is_empty());
+        // 6m square minus the 1.5m square of sub cells centered inside the pillar inflated by 0.3m
+        let total: f32 = mesh.polygons.iter().map(|p| area(p)).sum();
+        assert!((total - (36. - 1.5 * 1.5)).abs() < 1e-4, "area {total}");
+        assert!(mesh.polygon_at(Vec2::new(3., 3.)).is_none());
+        assert!(mesh.polygon_at(Vec2::new(2.3, 3.)).is_none());
+        assert!(mesh.polygon_at(Vec2::new(0.5, 0.5)).is_some());
+        assert!(mesh.polygon_at(Vec2::new(5.5, 5.5)).is_some());
+        assert!(mesh.polygon_at(Vec2::new(6.5, 3.)).is_none());
+        for link in &mesh.links {
+            assert!(link.a < mesh.polygons.len() && link.b < mesh.polygons.len());
+            assert!(link.portal.0 != link.portal.1);
+        }
+    }
+
+    #[test]
+    fn find_path_goes_around_the_obstacle() {
+        let mesh = pillar_room();
+        let (start, goal) = (Vec2::new(0.5, 3.), Vec2::new(5.5, 3.));
+        let path = mesh.find_path(start, goal).unwrap();
+        assert_eq!(path.first(), Some(&start));
+        assert_eq!(path.last(), Some(&goal));
+        assert!(path.len() > 2, "path {path:?}");
+        let blocked = Rect::new(2.25, 2.25, 3.75, 3.75);
+        for pair in path.windows(2) {
+            for i in 0..=100 {
+                let p = pair[0].lerp(pair[1], i as f32 / 100.);
+                let inside = p.cmpgt(blocked.min).all() && p.cmplt(blocked.max).all();
+                assert!(!inside, "{p} crosses the obstacle, path {path:?}");
+            }
+        }
+        // tight around two corners of the blocked area
+        let length: f32 = path.windows(2).map(|p| p[0].distance(p[1])).sum();
+        let shortest = 2. * Vec2::new(1.75, 0.75).length() + 1.5;
+        assert!(
+            (length - shortest).abs() < 1e-3,
+            "length {length}, path {path:?}"
+        );
+    }
+}
@@ -4,7 +4,7 @@ use bevy::{
 };
 use rand::prelude::*;
 
-use crate::ui::basic_materials::BasicMaterials;
+use crate::{ai::terrain::Terrain, ui::basic_materials::BasicMaterials};
 
 pub struct SwarmPlugin;
 
@@ -32,11 +32,13 @@ pub struct SwarmStats {
 
 const SPAWN_MAX: f32 = 100.;
 const NPC_NUM: u32 = 10000;
+/// Above the terrain.
 const HEIGHT: f32 = 0.6;
 const FORCE: f32 = 10.;
 
 fn init_swarm(
     materials: Res<BasicMaterials>,
+    terrain: Res<Terrain>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut ev_init_swarm: EventReader<InitSwarmEvent>,
     q_swarm_npcs: Query<Entity, With<SwarmNPC>>,
@@ -55,7 +57,11 @@ fn init_swarm(
                 let cube_ent = cmd
                     .spawn((
                         PbrBundle {
-                            transform: Transform::from_translation(Vec3::new(x, HEIGHT, z)),
+                            transform: Transform::from_translation(Vec3::new(
+                                x,
+                                terrain.height_at(Vec2::new(x, z)) + HEIGHT,
+                                z,
+                            )),
                             mesh: cube.clone(),
                             material: materials.salmon.clone(),
                             ..default()
@@ -79,21 +85,21 @@ fn init_swarm(
 
 fn move_swarm(
     time: Res<Time>,
+    terrain: Res<Terrain>,
     mut swarm_stats: ResMut<SwarmStats>,
     mut q_swarm_npcs: Query<(Entity, &mut Transform, &mut SwarmNPC)>,
     mut cmd: Commands,
 ) {
     for (ent, mut tr, mut npc) in &mut q_swarm_npcs {
-        let dist = tr.translation.length();
+        let dist = tr.translation.xz().length();
         let force = FORCE / dist.powi(2);
         npc.speed += force * time.delta_seconds();
         if npc.speed > dist - 1. || dist < 1. {
             cmd.entity(ent).despawn_recursive();
             swarm_stats.hits += 1;
         } else {
-            let mut dir = -tr.translation.normalize();
-            dir.y = 0.;
-            tr.translation += npc.speed * dir;
+            let xz = tr.translation.xz() - npc.speed * tr.translation.xz().normalize();
+            tr.translation = Vec3::new(xz.x, terrain.height_at(xz) + HEIGHT, xz.y);
         }
     }
     if swarm_stats.hits > 0 && time.elapsed_seconds() - swarm_stats.last_elapsed_sec >= 1. {
@@ -1,7 +1,20 @@
-use bevy::prelude::*;
+use bevy::{
+    asset::LoadState,
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
 use bevy_xpbd_3d::prelude::*;
 
-use crate::ui::{basic_materials::BasicMaterials, selection::Layer};
+use crate::{
+    ai::building_path::BuildingAgent,
+    camera::MainCamera,
+    mesh::heightmap::{HeightNoise, Heightmap},
+    ui::{
+        basic_materials::{BasicMaterials, TerrainMaterial},
+        selection::Layer,
+        side_panel::{SidePanel, UiMode},
+    },
+};
 
 pub struct TerrainPlugin;
 
@@ -9,81 +22,856 @@ impl Plugin for TerrainPlugin {
     fn build(&self, app: &mut App) {
         app.register_type::<Terrain>()
             .init_resource::<Terrain>()
-            .add_systems(Startup, setup_terrain)
-            .add_systems(Update, draw_terrain_lines);
+            .add_systems(
+                Update,
+                (
+                    (
+                        load_terrain,
+                        sculpt_terrain,
+                        stream_terrain,
+                        stream_terrain_colliders,
+                    )
+                        .chain(),
+                    draw_terrain_lines,
+                    measure_terrain,
+                    update_terrain_material,
+                ),
+            );
     }
 }
 
-#[derive(Resource, Default, Reflect)]
+/// Where terrain heights come from.
+#[derive(Clone, Debug, Reflect)]
+pub enum TerrainSource {
+    Flat,
+    /// A grayscale image asset, one sample per pixel, black at 0 & white at `max_height`.
+    /// Replaced by `Flat` if it fails to load.
+    Image {
+        path: String,
+        max_height: f32,
+    },
+    Noise(HeightNoise),
+}
+
+/// The ground: a grid of chunks, with separate meshes & heightfield colliders.
+///
+/// Meshes are loaded around `MainCamera::focus`, with less detail further away.
+/// Colliders are always at full detail, under every moving body & agent, & around the focus for picking.
+/// Set `dirty` to reload everything, e.g. after changing the source.
+#[derive(Resource, Reflect)]
 #[reflect(Resource)]
 pub struct Terrain {
+    pub source: TerrainSource,
+    /// Extent on X & Z of flat & noise terrain, centered on the origin.
+    /// Image terrain is as large as the image.
+    pub size: Vec2,
+    pub cell_size: f32,
+    /// Number of cells on each side of a chunk, at full detail. A power of two.
+    pub chunk_cells: u32,
+    /// Chunks further than this from the focus are unloaded.
+    pub view_distance: f32,
+    /// Chunks halve their detail every `lod_distance` away from the focus.
+    pub lod_distance: f32,
+    pub max_lod: u32,
+    /// Colliders are also loaded this far from the focus, so the ground can be clicked.
+    pub physics_distance: f32,
+    pub overlay: TerrainOverlay,
+    pub dirty: bool,
+    /// Parent of the chunks.
     pub ground: Option<Entity>,
+    /// Render chunks.
+    #[reflect(ignore)]
+    pub chunks: HashMap<IVec2, Entity>,
+    /// Physics chunks.
+    #[reflect(ignore)]
+    pub colliders: HashMap<IVec2, Entity>,
+    /// Heights of image terrain.
+    #[reflect(ignore)]
+    pub heightmap: Option<Heightmap>,
+    /// Height changes made by sculpting, per full detail sample.
+    #[reflect(ignore)]
+    pub height_edits: HashMap<IVec2, f32>,
+    /// Weights of the `TERRAIN_LAYERS`, per full detail sample. Unpainted samples are all on the first layer.
+    #[reflect(ignore)]
+    pub paint: HashMap<IVec2, Vec4>,
+    /// Chunks to rebuild after edits.
+    #[reflect(ignore)]
+    stale_chunks: HashSet<IVec2>,
+    #[reflect(ignore)]
+    stale_colliders: HashSet<IVec2>,
+}
+
+impl Default for Terrain {
+    fn default() -> Self {
+        Self {
+            source: TerrainSource::Noise(HeightNoise::default()),
+            size: Vec2::splat(4000.),
+            cell_size: 1.,
+            chunk_cells: 32,
+            view_distance: 500.,
+            lod_distance: 100.,
+            max_lod: 4,
+            physics_distance: 250.,
+            overlay: TerrainOverlay::default(),
+            dirty: true,
+            ground: None,
+            chunks: HashMap::default(),
+            colliders: HashMap::default(),
+            heightmap: None,
+            height_edits: HashMap::default(),
+            paint: HashMap::default(),
+            stale_chunks: HashSet::default(),
+            stale_colliders: HashSet::default(),
+        }
+    }
+}
+
+/// Grid drawn on the `Terrain` around the camera focus.
+#[derive(Clone, Debug, Reflect)]
+pub struct TerrainOverlay {
+    pub enabled: bool,
+    /// Distance between minor lines.
+    pub spacing: f32,
+    /// Every that many lines is a major line. The axes are always drawn.
+    pub major_every: u32,
+    pub show_minor: bool,
+    pub major_color: Color,
+    pub minor_color: Color,
+    /// Half size of the drawn area.
+    pub extent: f32,
+    /// Lines fade out from this distance to `extent`.
+    pub fade_start: f32,
+    /// Follows the ground, instead of lying flat at the focus height.
+    pub drape: bool,
+    /// Height above the ground, against z-fighting.
+    pub lift: f32,
+}
+
+impl Default for TerrainOverlay {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            spacing: 2.,
+            major_every: 5,
+            show_minor: true,
+            major_color: Color::rgba(1., 1., 1., 0.5),
+            minor_color: Color::rgba(1., 1., 1., 0.15),
+            extent: 60.,
+            fade_start: 30.,
+            drape: true,
+            lift: 0.05,
+        }
+    }
+}
+
+/// A loaded part of the `Terrain`'s mesh.
+#[derive(Component, Clone, PartialEq, Eq, Debug)]
+pub struct TerrainChunk {
+    pub coord: IVec2,
+    /// Cells are `2^lod` times larger than at full detail.
+    pub lod: u32,
+    /// Of the neighbors on the +X, -X, +Z & -Z sides, for stitching.
+    pub neighbor_lods: [u32; 4],
+}
+
+/// Names & default colors of the layers painted in `UiMode::PaintTerrain`.
+///
+/// With auto texturing on, the first layer is picked by slope & altitude.
+pub const TERRAIN_LAYERS: [(&str, Color); 4] = [
+    ("Ground", Color::SILVER),
+    ("Grass", Color::rgb(0.45, 0.75, 0.35)),
+    ("Rock", Color::rgb(0.45, 0.42, 0.4)),
+    ("Sand", Color::rgb(0.95, 0.85, 0.6)),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BrushTool {
+    Raise,
+    Lower,
+    Smooth,
+    /// Towards a height.
+    Flatten(f32),
+    /// A layer of `TERRAIN_LAYERS`.
+    Paint(usize),
+}
+
+/// Terrain edit, applied continuously while the mouse is pressed.
+#[derive(Clone, Copy, Debug)]
+pub struct TerrainBrush {
+    pub tool: BrushTool,
+    pub radius: f32,
+    /// Meters per second for `Raise` & `Lower`, rate of approach for the others.
+    pub strength: f32,
 }
 
-fn setup_terrain(
+const CHUNK_SIDES: [IVec2; 4] = [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y];
+/// Chunks (re)built per frame, nearest first.
+const CHUNK_BUILDS_PER_FRAME: usize = 8;
+
+impl Terrain {
+    /// Whether an entity is a terrain collider.
+    pub fn is_ground(&self, entity: Entity) -> bool {
+        self.colliders.values().any(|e| *e == entity)
+    }
+
+    /// The chunk containing an XZ position.
+    pub fn chunk_at(&self, point: Vec2) -> IVec2 {
+        (point / self.chunk_size()).floor().as_ivec2()
+    }
+
+    /// Whether heights are available, e.g. not waiting for an image.
+    pub fn is_ready(&self) -> bool {
+        !matches!(self.source, TerrainSource::Image { .. }) || self.heightmap.is_some()
+    }
+
+    /// XZ bounds.
+    pub fn rect(&self) -> Rect {
+        match (&self.source, &self.heightmap) {
+            (TerrainSource::Image { .. }, Some(heightmap)) => heightmap.rect(),
+            _ => Rect::from_center_size(Vec2::ZERO, self.size),
+        }
+    }
+
+    /// Height of the full detail grid point `sample`, at `sample * cell_size`.
+    pub fn sample(&self, sample: IVec2) -> f32 {
+        self.source_sample(sample) + self.height_edits.get(&sample).unwrap_or(&0.)
+    }
+
+    /// Height of a sample before edits.
+    fn source_sample(&self, sample: IVec2) -> f32 {
+        match &self.source {
+            TerrainSource::Flat => 0.,
+            TerrainSource::Noise(noise) => noise.sample(sample.as_vec2() * self.cell_size),
+            TerrainSource::Image { .. } => self.heightmap.as_ref().map_or(0., |heightmap| {
+                let origin = (heightmap.origin / heightmap.cell_size).round().as_ivec2();
+                heightmap.sample(sample - origin)
+            }),
+        }
+    }
+
+    /// Layer weights of a sample, summing to 1.
+    pub fn layers(&self, sample: IVec2) -> Vec4 {
+        self.paint.get(&sample).copied().unwrap_or(Vec4::X)
+    }
+
+    /// Applies a brush for `dt` seconds around an XZ position.
+    pub fn apply_brush(&mut self, brush: &TerrainBrush, center: Vec2, dt: f32) {
+        let min = ((center - brush.radius) / self.cell_size)
+            .floor()
+            .as_ivec2();
+        let max = ((center + brush.radius) / self.cell_size).ceil().as_ivec2();
+        let weighted: Vec<(IVec2, f32)> = (min.y..=max.y)
+            .flat_map(|z| (min.x..=max.x).map(move |x| IVec2::new(x, z)))
+            .filter_map(|sample| {
+                let distance = (sample.as_vec2() * self.cell_size).distance(center);
+                let t = 1. - distance / brush.radius.max(0.01);
+                // smooth falloff towards the rim
+                (t > 0.).then_some((sample, t * t * (3. - 2. * t)))
+            })
+            .collect();
+        let rate = |weight: f32| (weight * brush.strength * dt).min(1.);
+
+        if let BrushTool::Paint(layer) = brush.tool {
+            let target = Vec4::AXES[layer.min(3)];
+            for (sample, weight) in weighted {
+                let layers = self.layers(sample);
+                self.paint.insert(sample, layers.lerp(target, rate(weight)));
+            }
+        } else {
+            // computed from the heights before this step
+            let changes: Vec<(IVec2, f32)> = weighted
+                .iter()
+                .map(|(sample, weight)| {
+                    let height = self.sample(*sample);
+                    let change = match brush.tool {
+                        BrushTool::Raise => weight * brush.strength * dt,
+                        BrushTool::Lower => -weight * brush.strength * dt,
+                        BrushTool::Smooth => {
+                            let average = CHUNK_SIDES
+                                .iter()
+                                .map(|side| self.sample(*sample + *side))
+                                .sum::<f32>()
+                                / 4.;
+                            (average - height) * rate(*weight)
+                        }
+                        BrushTool::Flatten(target) => (target - height) * rate(*weight),
+                        BrushTool::Paint(_) => 0.,
+                    };
+                    (*sample, change)
+                })
+                .collect();
+            for (sample, change) in changes {
+                *self.height_edits.entry(sample).or_default() += change;
+            }
+        }
+
+        // chunks whose samples, or their neighbors for normals, have changed
+        let margin = self.cell_size * (1 << self.max_lod) as f32;
+        let min = ((center - brush.radius - margin) / self.chunk_size())
+            .floor()
+            .as_ivec2();
+        let max = ((center + brush.radius + margin) / self.chunk_size())
+            .floor()
+            .as_ivec2();
+        for z in min.y..=max.y {
+            for x in min.x..=max.x {
+                self.stale_chunks.insert(IVec2::new(x, z));
+                self.stale_colliders.insert(IVec2::new(x, z));
+            }
+        }
+    }
+
+    /// Ground height at an XZ position, as on the full detail mesh.
+    pub fn height_at(&self, point: Vec2) -> f32 {
+        let grid = point / self.cell_size;
+        let cell = grid.floor();
+        let t = grid - cell;
+        let cell = cell.as_ivec2();
+        let h = |dx, dz| self.sample(cell + IVec2::new(dx, dz));
+        let top = h(0, 0) + (h(1, 0) - h(0, 0)) * t.x;
+        let bottom = h(0, 1) + (h(1, 1) - h(0, 1)) * t.x;
+        top + (bottom - top) * t.y
+    }
+
+    /// Ground normal at an XZ position.
+    pub fn normal_at(&self, point: Vec2) -> Vec3 {
+        let d = self.cell_size;
+        let dx = self.height_at(point + Vec2::X * d) - self.height_at(point - Vec2::X * d);
+        let dz = self.height_at(point + Vec2::Y * d) - self.height_at(point - Vec2::Y * d);
+        Vec3::new(-dx, 2. * d, -dz).normalize()
+    }
+
+    /// Side length of a chunk.
+    pub fn chunk_size(&self) -> f32 {
+        self.chunk_cells as f32 * self.cell_size
+    }
+
+    /// XZ bounds of a chunk.
+    pub fn chunk_rect(&self, coord: IVec2) -> Rect {
+        let min = coord.as_vec2() * self.chunk_size();
+        Rect::from_corners(min, min + Vec2::splat(self.chunk_size()))
+    }
+
+    /// Level of detail of a chunk, by its distance from `focus`.
+    pub fn chunk_lod(&self, coord: IVec2, focus: Vec2) -> u32 {
+        let rect = self.chunk_rect(coord);
+        let distance = focus.distance(focus.clamp(rect.min, rect.max));
+        let max_lod = self.max_lod.min(self.chunk_cells.max(1).ilog2());
+        ((distance / self.lod_distance.max(0.01)) as u32).min(max_lod)
+    }
+
+    /// Chunks within `distance` of `focus`, inside the bounds.
+    pub fn chunks_within(&self, focus: Vec2, distance: f32) -> impl Iterator<Item = IVec2> + '_ {
+        let bounds = self.rect();
+        let area = bounds.intersect(Rect::from_center_half_size(focus, Vec2::splat(distance)));
+        let (min, max) = if area.is_empty() {
+            (IVec2::ZERO, IVec2::ZERO)
+        } else {
+            (
+                (area.min / self.chunk_size()).floor().as_ivec2(),
+                (area.max / self.chunk_size()).ceil().as_ivec2(),
+            )
+        };
+        (min.y..max.y)
+            .flat_map(move |z| (min.x..max.x).map(move |x| IVec2::new(x, z)))
+            .filter(move |coord| {
+                let rect = self.chunk_rect(*coord);
+                focus.distance(focus.clamp(rect.min, rect.max)) <= distance
+                    && !rect.intersect(bounds).is_empty()
+            })
+    }
+
+    /// Chunks to load around `focus`, with their level of detail.
+    pub fn chunks_around(&self, focus: Vec2) -> HashMap<IVec2, u32> {
+        self.chunks_within(focus, self.view_distance)
+            .map(|coord| (coord, self.chunk_lod(coord, focus)))
+            .collect()
+    }
+
+    /// Full detail heightfield of a chunk & its XZ center.
+    pub fn chunk_collider(&self, coord: IVec2) -> (Vec2, Collider) {
+        let heightmap = self.chunk_heightmap(&TerrainChunk {
+            coord,
+            lod: 0,
+            neighbor_lods: [0; 4],
+        });
+        let cells = heightmap.cells - UVec2::splat(2);
+        let size = cells.as_vec2() * heightmap.cell_size;
+        (
+            heightmap.chunk_center(UVec2::ONE, cells),
+            Collider::heightfield(
+                heightmap.chunk_heights(UVec2::ONE, cells),
+                Vec3::new(size.x, 1., size.y),
+            ),
+        )
+    }
+
+    /// Layer weights of a chunk, in `chunk_mesh` order. They are the vertex colors of the `TerrainMaterial`.
+    pub fn chunk_layers(&self, chunk: &TerrainChunk) -> Vec<[f32; 4]> {
+        let step = 1 << chunk.lod;
+        let cells = (self.chunk_cells.max(1) / step) as i32;
+        let first = chunk.coord * self.chunk_cells as i32;
+        (0..=cells)
+            .flat_map(|z| (0..=cells).map(move |x| IVec2::new(x, z)))
+            .map(|sample| self.layers(first + sample * step as i32).into())
+            .collect()
+    }
+
+    /// Heights of a chunk at its level of detail, with a border of one sample for normals,
+    /// & stitched to coarser neighbors.
+    pub fn chunk_heightmap(&self, chunk: &TerrainChunk) -> Heightmap {
+        let step = 1 << chunk.lod;
+        let cells = self.chunk_cells.max(1) / step;
+        let first = chunk.coord * self.chunk_cells as i32 - IVec2::splat(step as i32);
+        let mut heightmap = Heightmap::from_fn(
+            UVec2::splat(cells + 2),
+            self.cell_size * step as f32,
+            first.as_vec2() * self.cell_size,
+            |sample| self.sample(first + sample.as_ivec2() * step as i32),
+        );
+        for (side, lod) in CHUNK_SIDES.iter().zip(chunk.neighbor_lods) {
+            let ratio = 1 << lod.saturating_sub(chunk.lod);
+            heightmap.stitch(UVec2::ONE, UVec2::splat(cells), *side, ratio);
+        }
+        heightmap
+    }
+}
+
+fn load_terrain(
+    mut terrain: ResMut<Terrain>,
+    asset_server: Res<AssetServer>,
+    images: Res<Assets<Image>>,
+    mut image: Local<Option<Handle<Image>>>,
+    mut cmd: Commands,
+) {
+    if !terrain.dirty {
+        return;
+    }
+    if let TerrainSource::Image { path, max_height } = &terrain.source {
+        let handle = image.get_or_insert_with(|| asset_server.load(path.clone()));
+        let heightmap = if asset_server.get_load_state(handle.id()) == Some(LoadState::Failed) {
+            error!("Failed to load terrain image: {path}");
+            None
+        } else if !asset_server.is_loaded_with_dependencies(handle.id()) {
+            // wait for it
+            return;
+        } else {
+            let heightmap = images
+                .get(handle.id())
+                .and_then(|img| Heightmap::from_image(img, terrain.cell_size, *max_height));
+            if heightmap.is_none() {
+                error!("Unsupported terrain image: {path}");
+            }
+            heightmap
+        };
+        *image = None;
+        if heightmap.is_none() {
+            warn!("Falling back to flat terrain");
+            terrain.source = TerrainSource::Flat;
+        }
+        terrain.heightmap = heightmap;
+    } else {
+        terrain.heightmap = None;
+    }
+    terrain.dirty = false;
+
+    if let Some(ground) = terrain.ground {
+        cmd.entity(ground).despawn_recursive();
+    }
+    terrain.chunks.clear();
+    terrain.colliders.clear();
+    let ground = cmd.spawn(SpatialBundle::default()).id();
+    cmd.entity(ground)
+        .insert(Name::new(format!("Terrain ({ground:?})")));
+    terrain.ground = Some(ground);
+}
+
+fn stream_terrain(
     mut terrain: ResMut<Terrain>,
     mut meshes: ResMut<Assets<Mesh>>,
     materials: Res<BasicMaterials>,
+    q_camera: Query<&MainCamera>,
+    q_chunks: Query<(&TerrainChunk, &Handle<Mesh>)>,
     mut cmd: Commands,
 ) {
-    let ground_size = Vec3::new(200.0, 1.0, 200.0);
-
-    terrain.ground = Some({
-        let id = cmd
-            .spawn((
-                PbrBundle {
-                    transform: Transform::from_xyz(0.0, 0.0, 0.0),
-                    mesh: meshes.add(Mesh::from(shape::Box::new(
-                        ground_size.x,
-                        ground_size.y,
-                        ground_size.z,
-                    ))),
-                    material: materials.terrain.clone(),
-                    ..default()
-                },
-                RigidBody::Static,
-                Collider::cuboid(ground_size.x, ground_size.y, ground_size.z),
-                CollisionLayers::new([Layer::Object], [Layer::Object]),
-            ))
-            .id();
-        cmd.entity(id)
-            .insert(Name::new(format!("Terrain ({id:?})")));
-        id
-    });
+    let Some(ground) = terrain.ground else {
+        return;
+    };
+    if terrain.dirty || !terrain.is_ready() {
+        return;
+    }
+    let focus = q_camera
+        .get_single()
+        .map_or(Vec2::ZERO, |camera| camera.focus.xz());
+    let wanted = terrain.chunks_around(focus);
+
+    let unloaded: Vec<IVec2> = terrain
+        .chunks
+        .keys()
+        .filter(|coord| !wanted.contains_key(*coord))
+        .copied()
+        .collect();
+    for coord in unloaded {
+        if let Some(chunk) = terrain.chunks.remove(&coord) {
+            cmd.entity(chunk).despawn_recursive();
+        }
+    }
+
+    let mut builds: Vec<(TerrainChunk, f32)> = wanted
+        .iter()
+        .map(|(coord, lod)| TerrainChunk {
+            coord: *coord,
+            lod: *lod,
+            neighbor_lods: CHUNK_SIDES.map(|side| *wanted.get(&(*coord + side)).unwrap_or(lod)),
+        })
+        .filter(|chunk| {
+            terrain.stale_chunks.contains(&chunk.coord)
+                || terrain
+                    .chunks
+                    .get(&chunk.coord)
+                    .and_then(|e| q_chunks.get(*e).ok())
+                    .map(|(loaded, _)| loaded)
+                    != Some(chunk)
+        })
+        .map(|chunk| {
+            let distance = terrain.chunk_rect(chunk.coord).center().distance(focus);
+            (chunk, distance)
+        })
+        .collect();
+    builds.sort_by(|a, b| a.1.total_cmp(&b.1));
+    // edited chunks first, without a budget
+    let (edited, streamed): (Vec<_>, Vec<_>) = builds
+        .into_iter()
+        .map(|(chunk, _)| chunk)
+        .partition(|chunk| terrain.stale_chunks.contains(&chunk.coord));
+    terrain.stale_chunks.clear();
+
+    let uv_rect = terrain.rect();
+    for chunk in edited
+        .into_iter()
+        .chain(streamed.into_iter().take(CHUNK_BUILDS_PER_FRAME))
+    {
+        let heightmap = terrain.chunk_heightmap(&chunk);
+        let cells = heightmap.cells - UVec2::splat(2);
+        let center = heightmap.chunk_center(UVec2::ONE, cells);
+        let mut mesh = heightmap.chunk_mesh(UVec2::ONE, cells, uv_rect);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, terrain.chunk_layers(&chunk));
+        let loaded = terrain
+            .chunks
+            .get(&chunk.coord)
+            .and_then(|e| Some((*e, q_chunks.get(*e).ok()?.1)));
+        if let Some((entity, handle)) = loaded {
+            // in place
+            if let Some(loaded_mesh) = meshes.get_mut(handle) {
+                *loaded_mesh = mesh;
+            }
+            cmd.entity(entity).insert(chunk);
+        } else {
+            let mesh = meshes.add(mesh);
+            let coord = chunk.coord;
+            let entity = cmd
+                .spawn((
+                    chunk,
+                    MaterialMeshBundle {
+                        transform: Transform::from_xyz(center.x, 0., center.y),
+                        mesh,
+                        material: materials.terrain.clone(),
+                        ..default()
+                    },
+                    Name::new(format!("Terrain chunk ({}, {})", coord.x, coord.y)),
+                ))
+                .set_parent(ground)
+                .id();
+            terrain.chunks.insert(coord, entity);
+        }
+    }
 }
 
-fn draw_terrain_lines(mut gizmos: Gizmos) {
-    let col = Color::WHITE;
-    let ground_size = Vec3::new(200.0, 1.0, 200.0);
-    let h = 0.5;
+/// Physics part of the `Terrain`, at full detail.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TerrainCollider {
+    pub coord: IVec2,
+}
 
-    gizmos.line(
-        Vec3::new(-ground_size.x / 2., h, 0.),
-        Vec3::new(ground_size.x / 2., h, 0.),
-        col,
-    );
-    gizmos.line(
-        Vec3::new(0., h, -ground_size.z / 2.),
-        Vec3::new(0., h, ground_size.z / 2.),
-        col,
+fn stream_terrain_colliders(
+    mut terrain: ResMut<Terrain>,
+    q_camera: Query<&MainCamera>,
+    q_bodies: Query<(&RigidBody, &GlobalTransform), Without<TerrainCollider>>,
+    q_agents: Query<&GlobalTransform, With<BuildingAgent>>,
+    mut cmd: Commands,
+) {
+    let Some(ground) = terrain.ground else {
+        return;
+    };
+    if terrain.dirty || !terrain.is_ready() {
+        return;
+    }
+    let focus = q_camera
+        .get_single()
+        .map_or(Vec2::ZERO, |camera| camera.focus.xz());
+    let mut wanted: HashSet<IVec2> = terrain
+        .chunks_within(focus, terrain.physics_distance)
+        .collect();
+    // the chunks under & next to everything that moves
+    let movers = q_bodies
+        .iter()
+        .filter(|(body, _)| **body != RigidBody::Static)
+        .map(|(_, gtr)| gtr)
+        .chain(q_agents.iter());
+    let bounds = terrain.rect();
+    for gtr in movers {
+        let coord = terrain.chunk_at(gtr.translation().xz());
+        for z in -1..=1 {
+            for x in -1..=1 {
+                let coord = coord + IVec2::new(x, z);
+                if !terrain.chunk_rect(coord).intersect(bounds).is_empty() {
+                    wanted.insert(coord);
+                }
+            }
+        }
+    }
+
+    let unloaded: Vec<IVec2> = terrain
+        .colliders
+        .keys()
+        .filter(|coord| !wanted.contains(*coord))
+        .copied()
+        .collect();
+    for coord in unloaded {
+        if let Some(entity) = terrain.colliders.remove(&coord) {
+            cmd.entity(entity).despawn_recursive();
+        }
+    }
+
+    let builds: Vec<IVec2> = wanted
+        .into_iter()
+        .filter(|coord| {
+            !terrain.colliders.contains_key(coord) || terrain.stale_colliders.contains(coord)
+        })
+        .collect();
+    terrain.stale_colliders.clear();
+    for coord in builds {
+        let (center, collider) = terrain.chunk_collider(coord);
+        if let Some(entity) = terrain.colliders.get(&coord) {
+            cmd.entity(*entity).insert(collider);
+        } else {
+            let entity = cmd
+                .spawn((
+                    TerrainCollider { coord },
+                    SpatialBundle::from_transform(Transform::from_xyz(center.x, 0., center.y)),
+                    RigidBody::Static,
+                    collider,
+                    CollisionLayers::new([Layer::Object], [Layer::Object]),
+                    Name::new(format!("Terrain collider ({}, {})", coord.x, coord.y)),
+                ))
+                .set_parent(ground)
+                .id();
+            terrain.colliders.insert(coord, entity);
+        }
+    }
+}
+
+fn update_terrain_material(
+    panel: Res<SidePanel>,
+    basic_materials: Res<BasicMaterials>,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+) {
+    let auto_texture = panel.terrain_auto_texture as u32;
+    let Some(material) = materials.get(&basic_materials.terrain) else {
+        return;
+    };
+    // get_mut marks the material modified, so only on change
+    if material.extension.auto_texture != auto_texture {
+        if let Some(material) = materials.get_mut(&basic_materials.terrain) {
+            material.extension.auto_texture = auto_texture;
+        }
+    }
+}
+
+fn sculpt_terrain(
+    time: Res<Time>,
+    mouse: Res<Input<MouseButton>>,
+    spatial_query: SpatialQuery,
+    panel: Res<SidePanel>,
+    q_camera: Query<&MainCamera>,
+    mut terrain: ResMut<Terrain>,
+    mut flatten_height: Local<f32>,
+    mut gizmos: Gizmos,
+) {
+    let brush_mode = matches!(
+        panel.mode,
+        UiMode::SculptRaise
+            | UiMode::SculptLower
+            | UiMode::SculptSmooth
+            | UiMode::SculptFlatten
+            | UiMode::PaintTerrain
     );
-    gizmos.line(Vec3::new(0., h, 1.), Vec3::new(1., h, 0.), col);
-
-    let half_x = ground_size.x as i32 / 2 - 10;
-    for x in (-half_x..half_x + 1).step_by(10) {
-        gizmos.line(
-            Vec3::new(x as f32, h, -0.5),
-            Vec3::new(x as f32, h, 0.5),
-            col,
-        );
+    if !brush_mode || panel.mouse_over {
+        return;
+    }
+    let Ok(Some(ray)) = q_camera.get_single().map(|c| c.mouse_ray) else {
+        return;
+    };
+    let Some(hit) = spatial_query.cast_ray(
+        ray.origin,
+        ray.direction,
+        1000.,
+        false,
+        SpatialQueryFilter::new().with_masks([Layer::Object]),
+    ) else {
+        return;
+    };
+    if !terrain.is_ground(hit.entity) {
+        return;
     }
+    let center = (ray.origin + hit.time_of_impact * ray.direction).xz();
+    // flatten to the height under the first click of a stroke
+    if mouse.just_pressed(MouseButton::Left) {
+        *flatten_height = terrain.height_at(center);
+    }
+    let tool = match panel.mode {
+        UiMode::SculptRaise => BrushTool::Raise,
+        UiMode::SculptLower => BrushTool::Lower,
+        UiMode::SculptSmooth => BrushTool::Smooth,
+        UiMode::SculptFlatten => BrushTool::Flatten(*flatten_height),
+        _ => BrushTool::Paint(panel.paint_layer),
+    };
+    let brush = TerrainBrush {
+        tool,
+        radius: panel.brush_radius,
+        strength: panel.brush_strength,
+    };
 
-    let half_z = ground_size.z as i32 / 2 - 10;
-    for z in (-half_z..half_z + 1).step_by(10) {
-        gizmos.line(
-            Vec3::new(-0.5, h, z as f32),
-            Vec3::new(0.5, h, z as f32),
-            col,
-        );
+    let rim = (0..=32).map(|i| {
+        let angle = i as f32 / 32. * std::f32::consts::TAU;
+        let point = center + Vec2::from_angle(angle) * brush.radius;
+        Vec3::new(point.x, terrain.height_at(point) + 0.1, point.y)
+    });
+    gizmos.linestrip(rim, Color::YELLOW);
+
+    if mouse.pressed(MouseButton::Left) {
+        terrain.apply_brush(&brush, center, time.delta_seconds());
+    }
+}
+
+fn measure_terrain(
+    mouse: Res<Input<MouseButton>>,
+    spatial_query: SpatialQuery,
+    terrain: Res<Terrain>,
+    mut panel: ResMut<SidePanel>,
+    q_camera: Query<&MainCamera>,
+    mut gizmos: Gizmos,
+) {
+    if panel.mode != UiMode::Ruler {
+        return;
+    }
+    let hovered = q_camera
+        .get_single()
+        .ok()
+        .and_then(|camera| camera.mouse_ray)
+        .filter(|_| !panel.mouse_over)
+        .and_then(|ray| {
+            let hit = spatial_query.cast_ray(
+                ray.origin,
+                ray.direction,
+                1000.,
+                false,
+                SpatialQueryFilter::new().with_masks([Layer::Object]),
+            )?;
+            terrain
+                .is_ground(hit.entity)
+                .then_some(ray.origin + hit.time_of_impact * ray.direction)
+        });
+    if let (Some(point), true) = (hovered, mouse.just_pressed(MouseButton::Left)) {
+        if panel.ruler_start.is_none() || panel.ruler_end.is_some() {
+            panel.ruler_start = Some(point);
+            panel.ruler_end = None;
+        } else {
+            panel.ruler_end = Some(point);
+        }
+    }
+
+    let Some(start) = panel.ruler_start else {
+        return;
+    };
+    // follows the mouse until the second click
+    let Some(end) = panel.ruler_end.or(hovered) else {
+        return;
+    };
+    let (low, high) = if start.y < end.y {
+        (start, end)
+    } else {
+        (end, start)
+    };
+    let corner = Vec3::new(high.x, low.y, high.z);
+    gizmos.line(start, end, Color::YELLOW);
+    gizmos.line(low, corner, Color::ORANGE);
+    gizmos.line(corner, high, Color::ORANGE);
+    gizmos.sphere(start, Quat::IDENTITY, 0.1, Color::YELLOW);
+    gizmos.sphere(end, Quat::IDENTITY, 0.1, Color::YELLOW);
+}
+
+fn draw_terrain_lines(terrain: Res<Terrain>, q_camera: Query<&MainCamera>, mut gizmos: Gizmos) {
+    let overlay = &terrain.overlay;
+    if !overlay.enabled || !terrain.is_ready() {
+        return;
+    }
+    let focus = q_camera
+        .get_single()
+        .map_or(Vec2::ZERO, |camera| camera.focus.xz());
+    let area = terrain.rect().intersect(Rect::from_center_half_size(
+        focus,
+        Vec2::splat(overlay.extent),
+    ));
+    if area.is_empty() {
+        return;
+    }
+    let spacing = overlay.spacing.max(0.1);
+    let flat_height = terrain.height_at(focus);
+    let point = |xz: Vec2| {
+        let y = if overlay.drape {
+            terrain.height_at(xz)
+        } else {
+            flat_height
+        };
+        Vec3::new(xz.x, y + overlay.lift, xz.y)
+    };
+    let fade = |xz: Vec2| {
+        let t = (xz.distance(focus) - overlay.fade_start)
+            / (overlay.extent - overlay.fade_start).max(f32::EPSILON);
+        1. - t.clamp(0., 1.)
+    };
+    // draped lines bend at every cell
+    let step = if overlay.drape {
+        terrain.cell_size.min(spacing)
+    } else {
+        spacing
+    };
+
+    // lines along X, then along Z, as (across, along) pairs
+    for (across, along, axis) in [
+        (area.min.y..=area.max.y, area.min.x..=area.max.x, Vec2::X),
+        (area.min.x..=area.max.x, area.min.y..=area.max.y, Vec2::Y),
+    ] {
+        let first = (across.start() / spacing).ceil() as i32;
+        let last = (across.end() / spacing).floor() as i32;
+        for k in first..=last {
+            let color = if k == 0 {
+                Color::WHITE
+            } else if overlay.major_every > 0 && k % overlay.major_every as i32 == 0 {
+                overlay.major_color
+            } else if overlay.show_minor {
+                overlay.minor_color
+            } else {
+                continue;
+            };
+            let offset = axis.perp().abs() * k as f32 * spacing;
+            let n = ((along.end() - along.start()) / step).ceil() as i32;
+            gizmos.linestrip_gradient((0..=n).map(|i| {
+                let t = (along.start() + i as f32 * step).min(*along.end());
+                let xz = axis * t + offset;
+                (point(xz), color.with_a(color.a() * fade(xz)))
+            }));
+        }
     }
 }
@@ -0,0 +1,376 @@
+use bevy::{ecs::system::SystemParam, prelude::*, utils::HashMap};
+
+use super::{
+    rig::{KiBone, KiEffector, KiRevoluteJoint, KiSphericalJoint},
+    sequence::JointTarget,
+};
+
+/// Joint values to evaluate a rig at. Joints left out keep their current value.
+pub type JointPose = HashMap<Entity, JointTarget>;
+
+#[derive(Clone)]
+pub enum FkJoint {
+    Revolute(KiRevoluteJoint),
+    Spherical(KiSphericalJoint),
+}
+
+#[derive(Clone)]
+pub struct FkNode {
+    pub entity: Entity,
+    /// Index of the parent node, `None` for the root.
+    pub parent: Option<usize>,
+    pub local: Transform,
+    pub joint: Option<FkJoint>,
+    /// Length of the `KiBone`, if any. Joints & effectors below the bone sit at this distance from it.
+    pub bone: Option<f32>,
+    pub effector: bool,
+}
+
+/// Snapshot of a `KiRoot` hierarchy, evaluated without touching the live entities.
+#[derive(Clone)]
+pub struct FkModel {
+    /// Global transform of the root's parent.
+    pub base: Transform,
+    /// Parents always come before their children.
+    pub nodes: Vec<FkNode>,
+}
+
+/// Partial derivatives of the effector positions with respect to one degree of freedom.
+#[derive(Clone, Debug)]
+pub struct JacobianColumn {
+    pub joint: Entity,
+    /// World space rotation axis of this degree of freedom.
+    pub axis: Vec3,
+    /// One entry per effector, in `FkPose::effectors` order.
+    pub rows: Vec<Vec3>,
+}
+
+/// Result of `FkModel::solve`.
+#[derive(Clone, Debug)]
+pub struct FkPose {
+    /// World space transforms of all nodes.
+    pub globals: HashMap<Entity, Transform>,
+    pub effectors: Vec<Entity>,
+    /// Positional Jacobian. Revolute joints have one column (their right axis),
+    /// spherical joints three (the world axes).
+    pub jacobian: Vec<JacobianColumn>,
+}
+
+impl FkPose {
+    pub fn effector_position(&self, effector: Entity) -> Option<Vec3> {
+        self.globals.get(&effector).map(|g| g.translation)
+    }
+}
+
+impl FkModel {
+    /// Builds the model from a world, e.g. in a headless test.
+    pub fn from_world(world: &World, root: Entity) -> Self {
+        let base = world
+            .get::<Parent>(root)
+            .and_then(|p| world.get::<GlobalTransform>(p.get()))
+            .map_or(Transform::IDENTITY, |gtr| gtr.compute_transform());
+        Self::build(
+            root,
+            base,
+            |entity| {
+                let entity = world.get_entity(entity)?;
+                Some(FkNode {
+                    entity: entity.id(),
+                    parent: None,
+                    local: *entity.get::<Transform>()?,
+                    joint: fk_joint(
+                        entity.get::<KiRevoluteJoint>(),
+                        entity.get::<KiSphericalJoint>(),
+                    ),
+                    bone: entity.get::<KiBone>().map(|b| b.length),
+                    effector: entity.contains::<KiEffector>(),
+                })
+            },
+            |entity| {
+                world
+                    .get::<Children>(entity)
+                    .map(|c| c.to_vec())
+                    .unwrap_or_default()
+            },
+        )
+    }
+
+    fn build(
+        root: Entity,
+        base: Transform,
+        node: impl Fn(Entity) -> Option<FkNode>,
+        children: impl Fn(Entity) -> Vec<Entity>,
+    ) -> Self {
+        let mut nodes: Vec<FkNode> = vec![];
+        let mut stack = vec![(root, None)];
+        while let Some((entity, parent)) = stack.pop() {
+            let Some(mut fk_node) = node(entity) else {
+                continue;
+            };
+            fk_node.parent = parent;
+            let idx = nodes.len();
+            nodes.push(fk_node);
+            stack.extend(children(entity).into_iter().map(|c| (c, Some(idx))));
+        }
+        Self { base, nodes }
+    }
+
+    /// Current value of every joint in the model.
+    pub fn current_pose(&self) -> JointPose {
+        self.nodes
+            .iter()
+            .filter_map(|node| {
+                let target = match node.joint.as_ref()? {
+                    FkJoint::Revolute(joint) => JointTarget::Revolute(joint.get_angle(&node.local)),
+                    FkJoint::Spherical(joint) => {
+                        JointTarget::Spherical(joint.get_rotation(&node.local))
+                    }
+                };
+                Some((node.entity, target))
+            })
+            .collect()
+    }
+
+    /// World space transforms and Jacobian at `pose`. Joint values are clamped to the joint limits.
+    ///
+    /// Joints & effectors below a bone sit at the length of its `KiBone`, which the Jacobian follows.
+    pub fn solve(&self, pose: &JointPose) -> FkPose {
+        let mut globals: Vec<Transform> = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let mut local = node.local;
+            let bone = node
+                .parent
+                .and_then(|p| self.nodes[p].bone)
+                .filter(|_| node.joint.is_some() || node.effector);
+            if let Some(length) = bone {
+                local.translation = local.translation.normalize_or_zero() * length;
+            }
+            match (&node.joint, pose.get(&node.entity)) {
+                (Some(FkJoint::Revolute(joint)), Some(JointTarget::Revolute(angle))) => {
+                    joint.set_angle(&mut local, joint.clamp_angle(*angle));
+                }
+                (Some(FkJoint::Spherical(joint)), Some(JointTarget::Spherical(rot))) => {
+                    joint.set_rotation(&mut local, joint.clamp_rotation(*rot));
+                }
+                _ => {}
+            }
+            let parent = node.parent.map_or(self.base, |p| globals[p]);
+            globals.push(parent.mul_transform(local));
+        }
+
+        let effectors: Vec<usize> = (0..self.nodes.len())
+            .filter(|i| self.nodes[*i].effector)
+            .collect();
+        let mut jacobian = vec![];
+        for (i, node) in self.nodes.iter().enumerate() {
+            let axes = match node.joint {
+                Some(FkJoint::Revolute(_)) => vec![globals[i].right()],
+                Some(FkJoint::Spherical(_)) => vec![Vec3::X, Vec3::Y, Vec3::Z],
+                None => continue,
+            };
+            let pivot = globals[i].translation;
+            for axis in axes {
+                let rows = effectors
+                    .iter()
+                    .map(|e| {
+                        if self.is_ancestor(i, *e) {
+                            axis.cross(globals[*e].translation - pivot)
+                        } else {
+                            Vec3::ZERO
+                        }
+                    })
+                    .collect();
+                jacobian.push(JacobianColumn {
+                    joint: node.entity,
+                    axis,
+                    rows,
+                });
+            }
+        }
+
+        FkPose {
+            globals: self
+                .nodes
+                .iter()
+                .zip(globals)
+                .map(|(node, global)| (node.entity, global))
+                .collect(),
+            effectors: effectors.iter().map(|e| self.nodes[*e].entity).collect(),
+            jacobian,
+        }
+    }
+
+    fn is_ancestor(&self, ancestor: usize, mut node: usize) -> bool {
+        while let Some(parent) = self.nodes[node].parent {
+            if parent == ancestor {
+                return true;
+            }
+            node = parent;
+        }
+        false
+    }
+}
+
+fn fk_joint(
+    revolute: Option<&KiRevoluteJoint>,
+    spherical: Option<&KiSphericalJoint>,
+) -> Option<FkJoint> {
+    match (revolute, spherical) {
+        (Some(joint), _) => Some(FkJoint::Revolute(joint.clone())),
+        (_, Some(joint)) => Some(FkJoint::Spherical(joint.clone())),
+        _ => None,
+    }
+}
+
+/// Builds `FkModel`s from inside systems.
+#[derive(SystemParam)]
+pub struct RigFk<'w, 's> {
+    q_parent: Query<'w, 's, &'static Parent>,
+    q_children: Query<'w, 's, &'static Children>,
+    q_gtr: Query<'w, 's, &'static GlobalTransform>,
+    q_node: Query<
+        'w,
+        's,
+        (
+            &'static Transform,
+            Option<&'static KiRevoluteJoint>,
+            Option<&'static KiSphericalJoint>,
+            Option<&'static KiBone>,
+            Has<KiEffector>,
+        ),
+    >,
+}
+
+impl<'w, 's> RigFk<'w, 's> {
+    pub fn model(&self, root: Entity) -> FkModel {
+        let base = self
+            .q_parent
+            .get(root)
+            .ok()
+            .and_then(|p| self.q_gtr.get(p.get()).ok())
+            .map_or(Transform::IDENTITY, |gtr| gtr.compute_transform());
+        FkModel::build(
+            root,
+            base,
+            |entity| {
+                let (tr, revolute, spherical, bone, effector) = self.q_node.get(entity).ok()?;
+                Some(FkNode {
+                    entity,
+                    parent: None,
+                    local: *tr,
+                    joint: fk_joint(revolute, spherical),
+                    bone: bone.map(|b| b.length),
+                    effector,
+                })
+            },
+            |entity| {
+                self.q_children
+                    .get(entity)
+                    .map(|c| c.to_vec())
+                    .unwrap_or_default()
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two unit bones rotating about X, with an effector at the tip.
+    fn two_links(world: &mut World) -> (Entity, [Entity; 2], Entity) {
+        let effector = world
+            .spawn((
+                TransformBundle::from(Transform::from_xyz(0., 1., 0.)),
+                KiEffector,
+            ))
+            .id();
+        let forearm = world
+            .spawn((TransformBundle::default(), KiBone::new(1.)))
+            .push_children(&[effector])
+            .id();
+        let elbow = world
+            .spawn((
+                TransformBundle::from(Transform::from_xyz(0., 1., 0.)),
+                KiRevoluteJoint::new(1., Vec3::Y),
+            ))
+            .push_children(&[forearm])
+            .id();
+        let upper_arm = world
+            .spawn((TransformBundle::default(), KiBone::new(1.)))
+            .push_children(&[elbow])
+            .id();
+        let shoulder = world
+            .spawn((
+                TransformBundle::default(),
+                KiRevoluteJoint::new(1., Vec3::Y),
+            ))
+            .push_children(&[upper_arm])
+            .id();
+        let root = world
+            .spawn(TransformBundle::default())
+            .push_children(&[shoulder])
+            .id();
+        (root, [shoulder, elbow], effector)
+    }
+
+    #[test]
+    fn two_link_chain() {
+        let mut world = World::new();
+        let (root, [shoulder, elbow], effector) = two_links(&mut world);
+        let model = FkModel::from_world(&world, root);
+        assert_eq!(model.nodes.len(), 6);
+
+        let (a, b) = (0.4, 0.7);
+        let pose_at = |a: f32, b: f32| {
+            JointPose::from([
+                (shoulder, JointTarget::Revolute(a)),
+                (elbow, JointTarget::Revolute(b)),
+            ])
+        };
+        let pose = model.solve(&pose_at(a, b));
+        let position = pose.effector_position(effector).unwrap();
+        let expected = Vec3::new(0., a.cos() + (a + b).cos(), a.sin() + (a + b).sin());
+        assert!(
+            position.distance(expected) < 1e-5,
+            "effector at {position}, expected {expected}"
+        );
+        assert_eq!(pose.effectors, vec![effector]);
+
+        // the solved links are as long as their bones
+        for (i, node) in model.nodes.iter().enumerate() {
+            let Some(parent) = node.parent.map(|p| &model.nodes[p]) else {
+                continue;
+            };
+            let Some(bone) = parent.bone else {
+                continue;
+            };
+            let parent = parent.entity;
+            let link = pose.globals[&node.entity].translation - pose.globals[&parent].translation;
+            assert!(
+                (link.length() - bone).abs() < 1e-5,
+                "node {i} is {} from its bone of length {bone}",
+                link.length()
+            );
+        }
+        let mut longer = model.clone();
+        for node in &mut longer.nodes {
+            node.bone = node.bone.map(|length| length * 2.);
+        }
+        let position = longer.solve(&pose_at(a, b)).effector_position(effector);
+        assert!(position.unwrap().distance(expected * 2.) < 1e-5);
+
+        // the elbow column against central differences
+        let column = pose.jacobian.iter().find(|c| c.joint == elbow).unwrap();
+        let h = 1e-3;
+        let plus = model.solve(&pose_at(a, b + h)).effector_position(effector);
+        let minus = model.solve(&pose_at(a, b - h)).effector_position(effector);
+        let numeric = (plus.unwrap() - minus.unwrap()) / (2. * h);
+        assert!(
+            column.rows[0].distance(numeric) < 1e-3,
+            "analytic {}, numeric {numeric}",
+            column.rows[0]
+        );
+        assert_eq!(pose.jacobian.len(), 2);
+    }
+}
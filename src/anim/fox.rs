@@ -2,7 +2,7 @@ use bevy::{prelude::*, render::mesh::skinning::SkinnedMesh};
 use bevy_xpbd_3d::prelude::*;
 
 use crate::{
-    ai::terrain::Terrain,
+    ai::{building::Buildings, terrain::Terrain},
     camera::{MainCamera, ScreenPosition},
     ui::{
         basic_materials::BasicMaterials,
@@ -54,7 +54,6 @@ fn add_fox(
     let Ok(Some(ray)) = q_camera.get_single().map(|c| c.mouse_ray.clone()) else {
         return;
     };
-    let Some(ground) = terrain.ground else { return };
     let Some(hit) = spatial_query.cast_ray(
         ray.origin,
         ray.direction,
@@ -64,8 +63,9 @@ fn add_fox(
     ) else {
         return;
     };
-    if hit.entity == ground {
+    if terrain.is_ground(hit.entity) {
         let pos = ray.origin + hit.time_of_impact * ray.direction;
+        let pos = Vec3::new(pos.x, terrain.height_at(pos.xz()), pos.z);
         let dir_z = Vec3::new(ray.direction.x, 0., ray.direction.z).normalize();
         let dir_y = Vec3::Y;
         let rot = Quat::from_mat3(&Mat3::from_cols(
@@ -177,7 +177,6 @@ fn start_move_fox(
     let Ok(Some(ray)) = q_camera.get_single().map(|c| c.mouse_ray.clone()) else {
         return;
     };
-    let Some(ground) = terrain.ground else { return };
     let Some(hit) = spatial_query.cast_ray(
         ray.origin,
         ray.direction,
@@ -187,7 +186,7 @@ fn start_move_fox(
     ) else {
         return;
     };
-    if hit.entity == ground {
+    if terrain.is_ground(hit.entity) {
         let destination = ray.origin + hit.time_of_impact * ray.direction + 0.5 * Vec3::Y;
         for (fox_ent, fox) in &q_fox {
             if let Some(animator) = fox.animator {
@@ -209,15 +208,21 @@ fn start_move_fox(
     }
 }
 
+/// How high the fox can step up, e.g. from the ground onto a building's ground floor.
+const FOX_STEP: f32 = 0.6;
+
 fn move_fox(
     time: Res<Time>,
     anims: Res<Animations>,
+    terrain: Res<Terrain>,
+    buildings: Res<Buildings>,
     mut q_fox: Query<(Entity, &mut Transform, &Fox, &MoveFox)>,
     mut q_player: Query<&mut AnimationPlayer>,
     mut cmd: Commands,
 ) {
     for (fox_ent, mut fox_tr, fox, move_fox) in &mut q_fox {
-        if (move_fox.destination - fox_tr.translation).length() < 0.1 {
+        let to_destination = (move_fox.destination - fox_tr.translation).xz();
+        if to_destination.length() < 0.1 {
             cmd.entity(fox_ent).remove::<MoveFox>();
             if let Some(animator) = fox.animator {
                 if let Ok(mut player) = q_player.get_mut(animator) {
@@ -225,8 +230,16 @@ fn move_fox(
                 }
             }
         } else {
-            let dir = (move_fox.destination - fox_tr.translation).normalize();
-            fox_tr.translation += time.delta_seconds() * move_fox.speed * dir;
+            // walk along the ground, or the floor of the building it's in
+            let dir = to_destination.normalize();
+            let dir = Vec3::new(dir.x, 0., dir.y);
+            let step = (time.delta_seconds() * move_fox.speed).min(to_destination.length());
+            let pos = fox_tr.translation + step * dir;
+            let feet = pos + Vec3::Y * (FOX_STEP - 0.5);
+            let ground = buildings
+                .floor_height_at(feet)
+                .unwrap_or_else(|| terrain.height_at(pos.xz()));
+            fox_tr.translation = Vec3::new(pos.x, ground + 0.5, pos.z);
             let up = fox_tr.up();
             fox_tr.look_to(-dir, up);
         }
@@ -0,0 +1,280 @@
+use bevy::prelude::*;
+
+use super::{
+    joint::{RevoluteJointCommand, SphericalJointCommand},
+    rig::{KiEffector, KiLoop, KiRevoluteJoint, KiRoot, KiSphericalJoint, RigSystem},
+};
+
+pub struct IkPlugin;
+
+impl Plugin for IkPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<IkTarget>().add_systems(
+            PostUpdate,
+            (solve_ik, solve_loops).chain().in_set(RigSystem::Solve),
+        );
+    }
+}
+
+/// Inverse kinematics goal of a `KiEffector`.
+///
+/// The chain runs from the closest `KiRoot` ancestor down to the effector.
+/// Joints executing a joint command are treated as fixed by the solver.
+#[derive(Component, Reflect)]
+pub struct IkTarget {
+    /// World space position the effector should reach.
+    pub target: Vec3,
+    /// Max number of CCD passes per frame.
+    pub iterations: u32,
+    /// Distance under which the target counts as reached.
+    pub tolerance: f32,
+}
+
+impl IkTarget {
+    pub fn new(target: Vec3) -> Self {
+        Self {
+            target,
+            iterations: 10,
+            tolerance: 0.01,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum IkJoint {
+    Fixed,
+    /// Turns only about its local right axis, within the joint limits.
+    Revolute(KiRevoluteJoint),
+    /// Turns freely about its origin, within the joint limits.
+    Spherical(KiSphericalJoint),
+}
+
+#[derive(Clone)]
+pub struct IkLink {
+    pub entity: Entity,
+    pub local: Transform,
+    pub joint: IkJoint,
+}
+
+/// Snapshot of a root to effector chain, detached from the ECS so it can be solved in place.
+#[derive(Clone)]
+pub struct IkChain {
+    /// Global transform of the root's parent.
+    pub base: Transform,
+    /// From the root down to the effector, each relative to the previous one.
+    pub links: Vec<IkLink>,
+}
+
+impl IkChain {
+    pub fn globals(&self) -> Vec<Transform> {
+        let mut parent = self.base;
+        self.links
+            .iter()
+            .map(|link| {
+                parent = parent.mul_transform(link.local);
+                parent
+            })
+            .collect()
+    }
+
+    pub fn end(&self) -> Vec3 {
+        self.globals()
+            .last()
+            .map_or(self.base.translation, |g| g.translation)
+    }
+
+    /// Cyclic coordinate descent, from the effector upwards. Returns the remaining distance.
+    pub fn solve_ccd(&mut self, target: Vec3, iterations: u32, tolerance: f32) -> f32 {
+        let mut dist = self.end().distance(target);
+        for _ in 0..iterations {
+            if dist < tolerance {
+                break;
+            }
+            for i in (0..self.links.len()).rev() {
+                if matches!(self.links[i].joint, IkJoint::Fixed) {
+                    continue;
+                }
+                let globals = self.globals();
+                let end = globals.last().unwrap().translation;
+                let pivot = globals[i].translation;
+                let parent_rot = if i == 0 {
+                    self.base.rotation
+                } else {
+                    globals[i - 1].rotation
+                };
+                self.links[i].rotate_towards(parent_rot, end - pivot, target - pivot);
+            }
+            dist = self.end().distance(target);
+        }
+        dist
+    }
+}
+
+impl IkLink {
+    /// Turns the link so that `from` (world space, relative to the link origin) points to `to`,
+    /// as far as the joint type allows.
+    fn rotate_towards(&mut self, parent_rot: Quat, from: Vec3, to: Vec3) {
+        match &self.joint {
+            IkJoint::Fixed => {}
+            IkJoint::Revolute(joint) => {
+                let axis = parent_rot * self.local.right();
+                let from = from - from.dot(axis) * axis;
+                let to = to - to.dot(axis) * axis;
+                if from.length_squared() < 1e-8 || to.length_squared() < 1e-8 {
+                    return;
+                }
+                let angle = from.angle_between(to) * axis.dot(from.cross(to)).signum();
+                let local_axis = self.local.right();
+                self.local.rotate_axis(local_axis, angle);
+                joint.clamp_transform(&mut self.local);
+            }
+            IkJoint::Spherical(joint) => {
+                if from.length_squared() < 1e-8 || to.length_squared() < 1e-8 {
+                    return;
+                }
+                let rot = Quat::from_rotation_arc(from.normalize(), to.normalize());
+                self.local.rotation =
+                    (parent_rot.inverse() * rot * parent_rot * self.local.rotation).normalize();
+                joint.clamp_transform(&mut self.local);
+            }
+        }
+    }
+}
+
+type LinkQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static mut Transform,
+        Option<&'static KiRevoluteJoint>,
+        Option<&'static KiSphericalJoint>,
+        Has<RevoluteJointCommand>,
+        Has<SphericalJointCommand>,
+    ),
+>;
+
+/// Entities from the closest `KiRoot` ancestor down to `entity`, and the root's parent.
+fn chain_path(
+    entity: Entity,
+    q_root: &Query<Option<&Parent>, With<KiRoot>>,
+    q_parent: &Query<&Parent>,
+) -> Option<(Vec<Entity>, Option<Entity>)> {
+    let mut path = vec![entity];
+    for ancestor in q_parent.iter_ancestors(entity) {
+        path.push(ancestor);
+        if let Ok(parent) = q_root.get(ancestor) {
+            path.reverse();
+            return Some((path, parent.map(|p| p.get())));
+        }
+    }
+    None
+}
+
+fn build_chain(
+    path: &[Entity],
+    root_parent: Option<Entity>,
+    q_gtr: &Query<&GlobalTransform>,
+    q_link: &LinkQuery,
+) -> IkChain {
+    let base = root_parent
+        .and_then(|p| q_gtr.get(p).ok())
+        .map_or(Transform::IDENTITY, |gtr| gtr.compute_transform());
+
+    let mut links = Vec::with_capacity(path.len());
+    for entity in path {
+        let Ok((tr, revolute, spherical, revolute_cmd, spherical_cmd)) = q_link.get(*entity) else {
+            break;
+        };
+        let joint = match (revolute, spherical) {
+            (Some(joint), _) if !revolute_cmd => IkJoint::Revolute(joint.clone()),
+            (_, Some(joint)) if !spherical_cmd => IkJoint::Spherical(joint.clone()),
+            _ => IkJoint::Fixed,
+        };
+        links.push(IkLink {
+            entity: *entity,
+            local: *tr,
+            joint,
+        });
+    }
+    IkChain { base, links }
+}
+
+fn write_chain(chain: &IkChain, q_link: &mut LinkQuery) {
+    for link in chain
+        .links
+        .iter()
+        .filter(|l| !matches!(l.joint, IkJoint::Fixed))
+    {
+        if let Ok((mut tr, _, _, _, _)) = q_link.get_mut(link.entity) {
+            tr.rotation = link.local.rotation;
+        }
+    }
+}
+
+fn solve_ik(
+    q_effector: Query<(Entity, &IkTarget), With<KiEffector>>,
+    q_root: Query<Option<&Parent>, With<KiRoot>>,
+    q_parent: Query<&Parent>,
+    q_gtr: Query<&GlobalTransform>,
+    mut q_link: LinkQuery,
+) {
+    for (effector, ik_target) in &q_effector {
+        let Some((path, root_parent)) = chain_path(effector, &q_root, &q_parent) else {
+            continue;
+        };
+        let mut chain = build_chain(&path, root_parent, &q_gtr, &q_link);
+
+        if chain.end().distance(ik_target.target) < ik_target.tolerance {
+            continue;
+        }
+        chain.solve_ccd(ik_target.target, ik_target.iterations, ik_target.tolerance);
+        write_chain(&chain, &mut q_link);
+    }
+}
+
+fn solve_loops(
+    q_loop: Query<(Entity, &KiLoop)>,
+    q_root: Query<Option<&Parent>, With<KiRoot>>,
+    q_parent: Query<&Parent>,
+    q_gtr: Query<&GlobalTransform>,
+    mut q_link: LinkQuery,
+) {
+    for (a, ki_loop) in &q_loop {
+        let Some((path_a, root_parent)) = chain_path(a, &q_root, &q_parent) else {
+            continue;
+        };
+        let Some((path_b, _)) = chain_path(ki_loop.other, &q_root, &q_parent) else {
+            continue;
+        };
+        if path_a[0] != path_b[0] {
+            continue;
+        }
+        let mut chain_a = build_chain(&path_a, root_parent, &q_gtr, &q_link);
+        let mut chain_b = build_chain(&path_b, root_parent, &q_gtr, &q_link);
+        if chain_a.end().distance(chain_b.end()) < ki_loop.tolerance {
+            continue;
+        }
+
+        // shared joints move both points together, they can't close the loop
+        let shared = path_a
+            .iter()
+            .zip(path_b.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        for chain in [&mut chain_a, &mut chain_b] {
+            for link in chain.links.iter_mut().take(shared) {
+                link.joint = IkJoint::Fixed;
+            }
+        }
+
+        for _ in 0..ki_loop.iterations {
+            if chain_a.end().distance(chain_b.end()) < ki_loop.tolerance {
+                break;
+            }
+            chain_a.solve_ccd(chain_b.end(), 1, ki_loop.tolerance);
+            chain_b.solve_ccd(chain_a.end(), 1, ki_loop.tolerance);
+        }
+        write_chain(&chain_a, &mut q_link);
+        write_chain(&chain_b, &mut q_link);
+    }
+}
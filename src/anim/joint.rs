@@ -1,10 +1,14 @@
-use std::f32::consts::PI;
+use std::f32::consts::{FRAC_PI_2, PI};
 
-use bevy::{prelude::*, transform::TransformSystem};
+use bevy::{ecs::system::SystemParam, pbr::NotShadowCaster, prelude::*};
+use bevy_xpbd_3d::prelude::*;
 
-use crate::ui::basic_materials::BasicMaterials;
+use crate::{
+    mesh::{cone::Cone, sector::Sector},
+    ui::{basic_materials::BasicMaterials, selection::Layer},
+};
 
-use super::rig::{KiRevoluteJoint, KiSphericalJoint};
+use super::rig::{KiBone, KiRevoluteJoint, KiRoot, KiSphericalJoint, RigSystem};
 
 pub struct JointPlugin;
 
@@ -12,25 +16,176 @@ impl Plugin for JointPlugin {
     fn build(&self, app: &mut App) {
         app.register_type::<RevoluteJointCommand>()
             .register_type::<SphericalJointCommand>()
+            .add_event::<JointCollisionEvent>()
             .add_systems(
                 PostUpdate,
-                (update_revolute_joints, update_spherical_joints)
-                    .before(TransformSystem::TransformPropagate),
+                (update_revolute_joints, update_spherical_joints).in_set(RigSystem::Commands),
             )
             .add_systems(
                 Update,
-                (update_revolute_joint_mesh, update_spherical_joint_mesh),
+                (
+                    update_revolute_joint_mesh,
+                    update_spherical_joint_mesh,
+                    update_revolute_limit_mesh,
+                    update_spherical_limit_mesh,
+                ),
             );
     }
 }
 
+/// Velocity profile of a joint command. Accelerations are in radians per second squared.
+#[derive(Clone, Copy, PartialEq, Debug, Default, Reflect)]
+pub enum MotionProfile {
+    /// Full speed from start to end.
+    #[default]
+    Constant,
+    /// Linear speed ramps at the start & end.
+    Trapezoidal {
+        acceleration: f32,
+        deceleration: f32,
+    },
+    /// Sinusoidal speed ramps (limited jerk), with `acceleration` as peak acceleration.
+    SCurve { acceleration: f32 },
+}
+
+impl MotionProfile {
+    /// Time in seconds needed to cover `distance` radians with cruise `speed` (rad/s).
+    pub fn duration(&self, distance: f32, speed: f32) -> f32 {
+        let distance = distance.abs();
+        let speed = speed.max(f32::EPSILON);
+        match *self {
+            MotionProfile::Constant => distance / speed,
+            MotionProfile::Trapezoidal {
+                acceleration,
+                deceleration,
+            } => {
+                let (t_acc, t_cruise, t_dec) =
+                    trapezoid_phases(distance, speed, acceleration, deceleration);
+                t_acc + t_cruise + t_dec
+            }
+            MotionProfile::SCurve { acceleration } => {
+                let (speed, t_ramp) = s_curve_ramp(distance, speed, acceleration);
+                2. * t_ramp + (distance - speed * t_ramp) / speed
+            }
+        }
+    }
+
+    /// Distance in radians covered after `time` seconds, capped at `distance`.
+    pub fn position(&self, time: f32, distance: f32, speed: f32) -> f32 {
+        let distance = distance.abs();
+        let speed = speed.max(f32::EPSILON);
+        let duration = self.duration(distance, speed);
+        if time >= duration {
+            return distance;
+        }
+        let time = time.max(0.);
+        match *self {
+            MotionProfile::Constant => speed * time,
+            MotionProfile::Trapezoidal {
+                acceleration,
+                deceleration,
+            } => {
+                let (t_acc, t_cruise, t_dec) =
+                    trapezoid_phases(distance, speed, acceleration, deceleration);
+                let acc = acceleration.max(f32::EPSILON);
+                let peak = t_acc * acc;
+                if time < t_acc {
+                    acc * time * time / 2.
+                } else if time < t_acc + t_cruise {
+                    peak * t_acc / 2. + peak * (time - t_acc)
+                } else {
+                    let left = duration - time;
+                    distance - peak * left * left / (2. * t_dec.max(f32::EPSILON))
+                }
+            }
+            MotionProfile::SCurve { acceleration } => {
+                let (speed, t_ramp) = s_curve_ramp(distance, speed, acceleration);
+                let ramp = |t: f32| {
+                    if t_ramp > 0. {
+                        speed / 2. * (t - t_ramp / PI * (PI * t / t_ramp).sin())
+                    } else {
+                        speed * t
+                    }
+                };
+                if time < t_ramp {
+                    ramp(time)
+                } else if time < duration - t_ramp {
+                    ramp(t_ramp) + speed * (time - t_ramp)
+                } else {
+                    distance - ramp(duration - time)
+                }
+            }
+        }
+    }
+    /// Cruise speed needed to cover `distance` radians in `duration` seconds.
+    /// If the accelerations don't allow it, returns a speed that gets as close as possible.
+    pub fn speed_for_duration(&self, distance: f32, duration: f32) -> f32 {
+        let distance = distance.abs();
+        if distance < f32::EPSILON {
+            return 0.;
+        }
+        let duration = duration.max(f32::EPSILON);
+        // the duration only decreases with speed, so bisect between the constant speed
+        // & a speed high enough to be limited by the accelerations alone
+        let (mut lo, mut hi) = (distance / duration, 1000. * distance / duration);
+        for _ in 0..40 {
+            let mid = (lo + hi) / 2.;
+            if self.duration(distance, mid) > duration {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        hi
+    }
+}
+
+/// Durations of the acceleration, cruise & deceleration phases. When the distance is too short
+/// to reach `speed`, the profile becomes a triangle with no cruise phase.
+fn trapezoid_phases(
+    distance: f32,
+    speed: f32,
+    acceleration: f32,
+    deceleration: f32,
+) -> (f32, f32, f32) {
+    let (acc, dec) = (
+        acceleration.max(f32::EPSILON),
+        deceleration.max(f32::EPSILON),
+    );
+    let ramps = speed * speed / (2. * acc) + speed * speed / (2. * dec);
+    if ramps <= distance {
+        (speed / acc, (distance - ramps) / speed, speed / dec)
+    } else {
+        let peak = (2. * distance * acc * dec / (acc + dec)).sqrt();
+        (peak / acc, 0., peak / dec)
+    }
+}
+
+/// Peak speed & duration of each sinusoidal ramp. A ramp of length `t` up to speed `v`
+/// covers `v * t / 2` & peaks at acceleration `v * PI / (2 * t)`.
+fn s_curve_ramp(distance: f32, speed: f32, acceleration: f32) -> (f32, f32) {
+    let acc = acceleration.max(f32::EPSILON);
+    let ramp_time = |v: f32| v * PI / (2. * acc);
+    if speed * ramp_time(speed) <= distance {
+        (speed, ramp_time(speed))
+    } else {
+        let speed = (2. * acc * distance / PI).sqrt();
+        (speed, ramp_time(speed))
+    }
+}
+
 #[derive(Component, Reflect)]
 pub struct RevoluteJointCommand {
     pub target_angle: f32,
+    /// Cruise speed, in radians per second.
     pub speed: f32,
+    pub profile: MotionProfile,
     pub stop_at_collisions: bool,
     current_angle: f32,
-    last_non_colliding_angle: f32,
+    last_non_colliding_angle: Option<f32>,
+    start_angle: Option<f32>,
+    planned_angle: f32,
+    elapsed: f32,
 }
 
 impl RevoluteJointCommand {
@@ -38,46 +193,157 @@ impl RevoluteJointCommand {
         Self {
             target_angle,
             speed,
+            profile: MotionProfile::Constant,
             stop_at_collisions,
             current_angle: 0.0,
-            last_non_colliding_angle: 0.0,
+            last_non_colliding_angle: None,
+            start_angle: None,
+            planned_angle: 0.0,
+            elapsed: 0.0,
+        }
+    }
+
+    pub fn with_profile(mut self, profile: MotionProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+}
+
+/// Sent when a joint command stops because a bone below the joint hit something.
+#[derive(Event)]
+pub struct JointCollisionEvent {
+    pub joint: Entity,
+    pub bone: Entity,
+    /// The entity the bone collided with.
+    pub hit: Entity,
+}
+
+/// Radius of the capsule used for bones without their own collider.
+const BONE_RADIUS: f32 = 0.1;
+
+/// Checks the bones below a joint against `Layer::Object`, ignoring the rig's own colliders.
+///
+/// Bones use their own `Collider` if they have one, or a capsule along their length otherwise.
+/// Runs before transform propagation, so it sees the pose reached in the previous frame.
+#[derive(SystemParam)]
+pub struct BoneCollisions<'w, 's> {
+    spatial_query: SpatialQuery<'w, 's>,
+    q_parent: Query<'w, 's, &'static Parent>,
+    q_children: Query<'w, 's, &'static Children>,
+    q_root: Query<'w, 's, (), With<KiRoot>>,
+    q_bone: Query<
+        'w,
+        's,
+        (
+            &'static KiBone,
+            &'static GlobalTransform,
+            Option<&'static Collider>,
+        ),
+    >,
+}
+
+impl<'w, 's> BoneCollisions<'w, 's> {
+    /// Returns the first (bone, hit) pair found.
+    pub fn find_collision(&self, joint: Entity) -> Option<(Entity, Entity)> {
+        let root = std::iter::once(joint)
+            .chain(self.q_parent.iter_ancestors(joint))
+            .find(|e| self.q_root.contains(*e))
+            .unwrap_or(joint);
+        let rig: Vec<_> = std::iter::once(root)
+            .chain(self.q_children.iter_descendants(root))
+            .collect();
+
+        for bone_ent in self.q_children.iter_descendants(joint) {
+            let Ok((bone, gtr, collider)) = self.q_bone.get(bone_ent) else {
+                continue;
+            };
+            let (_, rot, pos) = gtr.to_scale_rotation_translation();
+            let filter = SpatialQueryFilter::new()
+                .with_masks([Layer::Object])
+                .without_entities(rig.iter().copied());
+            let hits = if let Some(collider) = collider {
+                self.spatial_query
+                    .shape_intersections(collider, pos, rot, filter)
+            } else {
+                let capsule =
+                    Collider::capsule((bone.length - 2. * BONE_RADIUS).max(0.), BONE_RADIUS);
+                self.spatial_query.shape_intersections(
+                    &capsule,
+                    pos + rot * (bone.length / 2. * Vec3::Y),
+                    rot,
+                    filter,
+                )
+            };
+            if let Some(hit) = hits.first() {
+                return Some((bone_ent, *hit));
+            }
         }
+        None
     }
 }
 
 fn update_revolute_joints(
+    time: Res<Time>,
     mut q_joint: Query<(
         Entity,
         &mut Transform,
         &KiRevoluteJoint,
         &mut RevoluteJointCommand,
     )>,
+    collisions: BoneCollisions,
+    mut ev_collision: EventWriter<JointCollisionEvent>,
     mut cmd: Commands,
 ) {
     for (entity, mut tr, joint, mut joint_cmd) in &mut q_joint {
         joint_cmd.current_angle = joint.get_angle(&tr);
-        joint_cmd.target_angle = joint_cmd.target_angle.clamp(-PI + 0.01, PI);
+        joint_cmd.target_angle = joint.clamp_angle(joint_cmd.target_angle);
 
-        let colliding = false;
+        let collision = if joint_cmd.stop_at_collisions {
+            collisions.find_collision(entity)
+        } else {
+            None
+        };
+        let colliding = collision.is_some();
         let mut cmd_finished = colliding;
 
-        if colliding {
-            joint_cmd.target_angle = joint_cmd.last_non_colliding_angle;
+        if let Some((bone, hit)) = collision {
+            ev_collision.send(JointCollisionEvent {
+                joint: entity,
+                bone,
+                hit,
+            });
+            joint_cmd.target_angle = joint_cmd
+                .last_non_colliding_angle
+                .unwrap_or(joint_cmd.current_angle);
         } else {
-            joint_cmd.last_non_colliding_angle = joint_cmd.current_angle;
+            joint_cmd.last_non_colliding_angle = Some(joint_cmd.current_angle);
         }
 
-        let diff = joint_cmd.target_angle - joint_cmd.current_angle;
-        if diff.abs() > 0.001 || colliding {
-            let rot_angle = if colliding {
-                diff
-            } else {
-                joint_cmd.speed.min(diff.abs()) * diff.signum()
-            };
-            let axis = tr.right();
-            tr.rotate_axis(axis, rot_angle);
+        let axis = tr.right();
+        if colliding {
+            tr.rotate_axis(axis, joint_cmd.target_angle - joint_cmd.current_angle);
         } else {
-            cmd_finished = true;
+            // (re)plan the motion when starting or when the target was changed meanwhile
+            if joint_cmd.start_angle.is_none() || joint_cmd.planned_angle != joint_cmd.target_angle
+            {
+                joint_cmd.start_angle = Some(joint_cmd.current_angle);
+                joint_cmd.planned_angle = joint_cmd.target_angle;
+                joint_cmd.elapsed = 0.;
+            }
+            joint_cmd.elapsed += time.delta_seconds();
+
+            let start = joint_cmd.start_angle.unwrap();
+            let distance = joint_cmd.target_angle - start;
+            let angle = start
+                + distance.signum()
+                    * joint_cmd
+                        .profile
+                        .position(joint_cmd.elapsed, distance, joint_cmd.speed);
+            tr.rotate_axis(axis, angle - joint_cmd.current_angle);
+
+            if joint_cmd.elapsed >= joint_cmd.profile.duration(distance, joint_cmd.speed) {
+                cmd_finished = true;
+            }
         }
 
         if cmd_finished {
@@ -157,11 +423,13 @@ fn update_revolute_joint_mesh(
 pub struct SphericalJointCommand {
     start_rot: Option<Quat>,
     pub target_rot: Quat,
+    /// Cruise speed, in radians per second.
     pub speed: f32,
+    pub profile: MotionProfile,
     pub stop_at_collisions: bool,
     current: f32,
     last_non_colliding: f32,
-    delta: f32,
+    elapsed: f32,
 }
 
 impl SphericalJointCommand {
@@ -170,10 +438,11 @@ impl SphericalJointCommand {
             start_rot: None,
             target_rot: target,
             speed,
+            profile: MotionProfile::Constant,
             stop_at_collisions,
             current: 0.,
             last_non_colliding: 0.,
-            delta: 0.,
+            elapsed: 0.,
         }
     }
 
@@ -188,53 +457,85 @@ impl SphericalJointCommand {
             start_rot: None,
             target_rot: Quat::from_euler(EulerRot::XZY, target_x, target_z, target_y),
             speed,
+            profile: MotionProfile::Constant,
             stop_at_collisions,
             current: 0.,
             last_non_colliding: 0.,
-            delta: 0.,
+            elapsed: 0.,
         }
     }
+
+    pub fn with_profile(mut self, profile: MotionProfile) -> Self {
+        self.profile = profile;
+        self
+    }
 }
 
 fn update_spherical_joints(
+    time: Res<Time>,
     mut q_joint: Query<(
         Entity,
         &mut Transform,
         &KiSphericalJoint,
         &mut SphericalJointCommand,
     )>,
+    collisions: BoneCollisions,
+    mut ev_collision: EventWriter<JointCollisionEvent>,
     mut cmd: Commands,
 ) {
     for (entity, mut tr, joint, mut joint_cmd) in &mut q_joint {
-        let colliding = false;
+        let collision = if joint_cmd.stop_at_collisions {
+            collisions.find_collision(entity)
+        } else {
+            None
+        };
+        let colliding = collision.is_some();
         let mut cmd_finished = colliding;
 
-        if joint_cmd.start_rot.is_none() {
-            joint_cmd.start_rot = Some((tr.rotation * joint.start_rot.inverse()).normalize());
-            let diff = {
-                let (t_ax, t_an) = joint_cmd.target_rot.to_axis_angle();
-                let (c_ax, c_an) = joint_cmd.start_rot.unwrap().to_axis_angle();
-                t_ax.angle_between(c_ax).abs() + (t_an - c_an).abs()
-            };
-            joint_cmd.delta = (joint_cmd.speed / diff).clamp(0., 1.);
-        } else {
-            joint_cmd.current = (joint_cmd.current + joint_cmd.delta).clamp(0., 1.);
-        }
-        if (1. - joint_cmd.current).abs() < 0.001 {
-            cmd_finished = true;
+        if let Some((bone, hit)) = collision {
+            ev_collision.send(JointCollisionEvent {
+                joint: entity,
+                bone,
+                hit,
+            });
         }
 
-        if colliding {
+        if joint_cmd.start_rot.is_none() {
+            joint_cmd.start_rot = Some(joint.get_rotation(&tr));
+            joint_cmd.target_rot = joint.clamp_rotation(joint_cmd.target_rot);
+        } else if colliding {
+            // the pose reached last frame collides, go back to the last one checked
             joint_cmd.current = joint_cmd.last_non_colliding;
         } else {
             joint_cmd.last_non_colliding = joint_cmd.current;
         }
 
-        let current_rot = joint_cmd
-            .start_rot
-            .unwrap()
-            .slerp(joint_cmd.target_rot, joint_cmd.current)
-            .normalize();
+        if !colliding {
+            joint_cmd.elapsed += time.delta_seconds();
+            let distance = joint_cmd
+                .start_rot
+                .unwrap()
+                .angle_between(joint_cmd.target_rot);
+            joint_cmd.current = if distance > 0.001 {
+                joint_cmd
+                    .profile
+                    .position(joint_cmd.elapsed, distance, joint_cmd.speed)
+                    / distance
+            } else {
+                1.
+            };
+            if joint_cmd.elapsed >= joint_cmd.profile.duration(distance, joint_cmd.speed) {
+                cmd_finished = true;
+            }
+        }
+
+        let current_rot = joint.clamp_rotation(
+            joint_cmd
+                .start_rot
+                .unwrap()
+                .slerp(joint_cmd.target_rot, joint_cmd.current)
+                .normalize(),
+        );
 
         tr.rotation = joint.start_rot;
         tr.rotate(current_rot);
@@ -305,3 +606,226 @@ fn update_spherical_joint_mesh(
         }
     }
 }
+
+/// Size of the limit meshes (sector radius, cone height).
+const LIMIT_MESH_SIZE: f32 = 0.5;
+
+/// Sector showing the allowed arc, fixed relative to the joint's parent.
+#[derive(Component, Reflect)]
+pub struct RevoluteJointLimitMesh {
+    min_angle: f32,
+    max_angle: f32,
+}
+
+fn update_revolute_limit_mesh(
+    materials: Res<BasicMaterials>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    q_joint: Query<(Entity, &Transform, &KiRevoluteJoint, Option<&Children>)>,
+    mut q_mesh: Query<(&mut Transform, &RevoluteJointLimitMesh), Without<KiRevoluteJoint>>,
+    mut cmd: Commands,
+) {
+    for (entity, tr, joint, children) in &q_joint {
+        let limit_mesh = children.and_then(|c| c.iter().find(|c| q_mesh.contains(**c)).copied());
+        if !joint.show_mesh {
+            if let Some(mesh_ent) = limit_mesh {
+                cmd.entity(mesh_ent).despawn_recursive();
+            }
+            continue;
+        }
+
+        // parent space frame with X along the hinge axis & Y along `start_dir`
+        let right = tr.right();
+        let up = (joint.start_dir - joint.start_dir.dot(right) * right).normalize_or_zero();
+        let frame = Quat::from_mat3(&Mat3::from_cols(right, up, right.cross(up)));
+        let rotation = tr.rotation.inverse() * frame;
+
+        if let Some(mesh_ent) = limit_mesh {
+            if let Ok((mut mesh_tr, limits)) = q_mesh.get_mut(mesh_ent) {
+                if limits.min_angle == joint.min_angle && limits.max_angle == joint.max_angle {
+                    mesh_tr.rotation = rotation;
+                    continue;
+                }
+            }
+            cmd.entity(mesh_ent).despawn_recursive();
+        }
+        cmd.entity(entity).with_children(|children| {
+            children.spawn((
+                PbrBundle {
+                    transform: Transform::from_rotation(rotation),
+                    mesh: meshes.add(Mesh::from(Sector::new(
+                        LIMIT_MESH_SIZE,
+                        joint.min_angle,
+                        joint.max_angle,
+                        32,
+                    ))),
+                    material: materials.ui_limits.clone(),
+                    ..default()
+                },
+                NotShadowCaster,
+                RevoluteJointLimitMesh {
+                    min_angle: joint.min_angle,
+                    max_angle: joint.max_angle,
+                },
+            ));
+        });
+    }
+}
+
+/// Swing cone & twist sector, fixed relative to the joint's parent.
+#[derive(Component, Reflect)]
+pub struct SphericalJointLimitMesh {
+    swing_limit: f32,
+    twist_min: f32,
+    twist_max: f32,
+}
+
+fn update_spherical_limit_mesh(
+    materials: Res<BasicMaterials>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    q_joint: Query<(Entity, &Transform, &KiSphericalJoint, Option<&Children>)>,
+    mut q_mesh: Query<(&mut Transform, &SphericalJointLimitMesh), Without<KiSphericalJoint>>,
+    mut cmd: Commands,
+) {
+    for (entity, tr, joint, children) in &q_joint {
+        let limit_mesh = children.and_then(|c| c.iter().find(|c| q_mesh.contains(**c)).copied());
+        if !joint.show_mesh {
+            if let Some(mesh_ent) = limit_mesh {
+                cmd.entity(mesh_ent).despawn_recursive();
+            }
+            continue;
+        }
+
+        let rotation = tr.rotation.inverse() * joint.start_rot;
+
+        if let Some(mesh_ent) = limit_mesh {
+            if let Ok((mut mesh_tr, limits)) = q_mesh.get_mut(mesh_ent) {
+                if limits.swing_limit == joint.swing_limit
+                    && limits.twist_min == joint.twist_min
+                    && limits.twist_max == joint.twist_max
+                {
+                    mesh_tr.rotation = rotation;
+                    continue;
+                }
+            }
+            cmd.entity(mesh_ent).despawn_recursive();
+        }
+        cmd.entity(entity).with_children(|children| {
+            children
+                .spawn((
+                    SpatialBundle::from_transform(Transform::from_rotation(rotation)),
+                    SphericalJointLimitMesh {
+                        swing_limit: joint.swing_limit,
+                        twist_min: joint.twist_min,
+                        twist_max: joint.twist_max,
+                    },
+                ))
+                .with_children(|parent| {
+                    // a cone can't show swings past 90 degrees, so those are left unbounded
+                    if joint.swing_limit < FRAC_PI_2 {
+                        parent.spawn((
+                            PbrBundle {
+                                transform: Transform::from_translation(
+                                    LIMIT_MESH_SIZE / 2. * Vec3::Y,
+                                )
+                                .with_rotation(Quat::from_rotation_x(PI)),
+                                mesh: meshes.add(Mesh::from(Cone::new(
+                                    LIMIT_MESH_SIZE * joint.swing_limit.tan(),
+                                    LIMIT_MESH_SIZE,
+                                    32,
+                                ))),
+                                material: materials.ui_limits.clone(),
+                                ..default()
+                            },
+                            NotShadowCaster,
+                        ));
+                    }
+                    // sector swept about the bone axis (Y), starting from Z
+                    parent.spawn((
+                        PbrBundle {
+                            transform: Transform::from_rotation(Quat::from_mat3(&Mat3::from_cols(
+                                Vec3::Y,
+                                Vec3::Z,
+                                Vec3::X,
+                            ))),
+                            mesh: meshes.add(Mesh::from(Sector::new(
+                                LIMIT_MESH_SIZE,
+                                joint.twist_min,
+                                joint.twist_max,
+                                32,
+                            ))),
+                            material: materials.ui_limits.clone(),
+                            ..default()
+                        },
+                        NotShadowCaster,
+                    ));
+                });
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks the analytic duration, & that the position rises continuously from 0 to `distance`.
+    fn check_profile(profile: MotionProfile, distance: f32, speed: f32, expected_duration: f32) {
+        let duration = profile.duration(distance, speed);
+        assert!(
+            (duration - expected_duration).abs() < 1e-4,
+            "{profile:?}: duration {duration}, expected {expected_duration}"
+        );
+        assert_eq!(profile.position(0., distance, speed), 0.);
+        assert_eq!(profile.position(duration, distance, speed), distance);
+        let steps = 1000;
+        let mut last = 0.;
+        for i in 1..=steps {
+            let position = profile.position(duration * i as f32 / steps as f32, distance, speed);
+            assert!(
+                position >= last - 1e-5,
+                "{profile:?}: moving back at step {i}"
+            );
+            // never faster than the cruise speed
+            let max_step = speed * duration / steps as f32;
+            assert!(
+                position - last <= max_step + 1e-4,
+                "{profile:?}: jump at step {i}"
+            );
+            last = position;
+        }
+        let almost = profile.position(duration - 1e-4, distance, speed);
+        assert!(
+            (almost - distance).abs() < 1e-3,
+            "{profile:?}: ends at {almost}"
+        );
+    }
+
+    #[test]
+    fn trapezoidal_reaches_target() {
+        let profile = MotionProfile::Trapezoidal {
+            acceleration: 2.,
+            deceleration: 1.,
+        };
+        // with a cruise phase: d / v + v / 2a + v / 2d
+        check_profile(profile, 2., 1., 2. + 0.25 + 0.5);
+        // too short to reach the cruise speed
+        let profile = MotionProfile::Trapezoidal {
+            acceleration: 2.,
+            deceleration: 2.,
+        };
+        check_profile(profile, 0.5, 2., 2. * (0.5f32 / 2.).sqrt());
+    }
+
+    #[test]
+    fn s_curve_reaches_target() {
+        let profile = MotionProfile::SCurve { acceleration: 2. };
+        // with a cruise phase: d / v + v * PI / 2a
+        check_profile(profile, 3., 1., 3. + PI / 4.);
+        // too short to reach the cruise speed
+        check_profile(profile, 0.1, 1., 2. * (PI * 0.1 / 4.).sqrt());
+    }
+
+    #[test]
+    fn constant_reaches_target() {
+        check_profile(MotionProfile::Constant, 2., 0.5, 4.);
+    }
+}
@@ -0,0 +1,241 @@
+use bevy::{prelude::*, utils::HashMap};
+use bevy_xpbd_3d::prelude::*;
+
+use crate::ui::selection::Layer;
+
+use super::{
+    ik::IkTarget,
+    rig::{KiEffector, KiRoot, KiRootDriver},
+};
+
+pub struct LocomotionPlugin;
+
+impl Plugin for LocomotionPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<KiFoot>()
+            .add_systems(Update, (drive_roots, step_feet).chain());
+    }
+}
+
+/// Horizontal distance under which a `KiRootDriver` waypoint counts as reached.
+const WAYPOINT_RADIUS: f32 = 0.1;
+/// How far above and below its expected position the ground is searched for.
+const GROUND_REACH: f32 = 1.;
+
+/// A `KiEffector` planted on the ground, stepping when it strays too far from its rest position.
+///
+/// Feet with the same `group` step together, and only while no other group of the rig is stepping.
+/// The foot is moved by setting its `IkTarget`.
+#[derive(Component, Reflect)]
+pub struct KiFoot {
+    /// Rest position, in the local space of the `KiRoot`.
+    pub rest: Vec3,
+    /// Distance from the rest position that triggers a step.
+    pub step_distance: f32,
+    /// Height of the step arc.
+    pub step_height: f32,
+    /// Seconds per step.
+    pub step_duration: f32,
+    pub group: u8,
+    planted: Option<Vec3>,
+    step: Option<FootStep>,
+}
+
+#[derive(Clone, Copy, Reflect)]
+struct FootStep {
+    from: Vec3,
+    to: Vec3,
+    elapsed: f32,
+}
+
+impl KiFoot {
+    pub fn new(rest: Vec3, group: u8) -> Self {
+        Self {
+            rest,
+            step_distance: 0.3,
+            step_height: 0.15,
+            step_duration: 0.25,
+            group,
+            planted: None,
+            step: None,
+        }
+    }
+
+    pub fn with_step(mut self, distance: f32, height: f32, duration: f32) -> Self {
+        self.step_distance = distance;
+        self.step_height = height;
+        self.step_duration = duration;
+        self
+    }
+
+    /// Where the foot rests on the ground, unless it is stepping.
+    pub fn planted(&self) -> Option<Vec3> {
+        self.step.is_none().then_some(self.planted).flatten()
+    }
+
+    pub fn is_stepping(&self) -> bool {
+        self.step.is_some()
+    }
+}
+
+/// Point of a step from `from` to `to` at `t` in `[0, 1]`, lifted by up to `height` mid way.
+pub fn step_arc(from: Vec3, to: Vec3, height: f32, t: f32) -> Vec3 {
+    let t = t.clamp(0., 1.);
+    let s = t * t * (3. - 2. * t);
+    from.lerp(to, s) + Vec3::Y * height * 4. * t * (1. - t)
+}
+
+/// Ground point below `point`, searched within `reach` above and below it.
+fn ground_point(
+    spatial_query: &SpatialQuery,
+    point: Vec3,
+    reach: f32,
+    rig: &[Entity],
+) -> Option<Vec3> {
+    let origin = point + Vec3::Y * reach;
+    spatial_query
+        .cast_ray(
+            origin,
+            Vec3::NEG_Y,
+            2. * reach,
+            true,
+            SpatialQueryFilter::new()
+                .with_masks([Layer::Object])
+                .without_entities(rig.iter().copied()),
+        )
+        .map(|hit| origin + Vec3::NEG_Y * hit.time_of_impact)
+}
+
+fn rig_entities(root: Entity, q_children: &Query<&Children>) -> Vec<Entity> {
+    std::iter::once(root)
+        .chain(q_children.iter_descendants(root))
+        .collect()
+}
+
+fn base_transform(parent: Option<&Parent>, q_gtr: &Query<&GlobalTransform>) -> Transform {
+    parent
+        .and_then(|p| q_gtr.get(p.get()).ok())
+        .map_or(Transform::IDENTITY, |gtr| gtr.compute_transform())
+}
+
+fn drive_roots(
+    time: Res<Time>,
+    spatial_query: SpatialQuery,
+    q_children: Query<&Children>,
+    q_gtr: Query<&GlobalTransform>,
+    mut q_root: Query<(Entity, &mut Transform, &mut KiRootDriver, Option<&Parent>), With<KiRoot>>,
+) {
+    let dt = time.delta_seconds();
+    for (root, mut tr, mut driver, parent) in &mut q_root {
+        let base = base_transform(parent, &q_gtr);
+        let mut global = base.mul_transform(*tr);
+
+        while let Some(waypoint) = driver.waypoints.front() {
+            if global.translation.xz().distance(waypoint.xz()) > WAYPOINT_RADIUS {
+                break;
+            }
+            driver.waypoints.pop_front();
+        }
+
+        let velocity = driver.desired_velocity(global.translation);
+        if velocity.length_squared() < 1e-6 && driver.ride_height.is_none() {
+            continue;
+        }
+        global.translation += velocity * dt;
+        if velocity.length_squared() > 1e-6 {
+            let target = Transform::IDENTITY.looking_to(velocity, Vec3::Y).rotation;
+            let angle = global.rotation.angle_between(target);
+            let max_angle = driver.turn_speed * dt;
+            global.rotation = if angle <= max_angle {
+                target
+            } else {
+                global.rotation.slerp(target, max_angle / angle)
+            };
+        }
+        if let Some(height) = driver.ride_height {
+            let rig = rig_entities(root, &q_children);
+            let expected = global.translation - Vec3::Y * height;
+            if let Some(ground) = ground_point(&spatial_query, expected, height, &rig) {
+                global.translation.y = ground.y + height;
+            }
+        }
+
+        *tr = Transform::from_matrix(base.compute_matrix().inverse() * global.compute_matrix());
+    }
+}
+
+fn step_feet(
+    time: Res<Time>,
+    spatial_query: SpatialQuery,
+    q_parent: Query<&Parent>,
+    q_children: Query<&Children>,
+    q_gtr: Query<&GlobalTransform>,
+    q_root: Query<(&Transform, Option<&Parent>, Option<&KiRootDriver>), With<KiRoot>>,
+    mut q_foot: Query<(Entity, &mut KiFoot, Option<&mut IkTarget>), With<KiEffector>>,
+    mut cmd: Commands,
+) {
+    let dt = time.delta_seconds();
+
+    let mut stepping = HashMap::new();
+    let mut feet = vec![];
+    for (foot_ent, foot, _) in &q_foot {
+        let Some(root) = q_parent
+            .iter_ancestors(foot_ent)
+            .find(|e| q_root.contains(*e))
+        else {
+            continue;
+        };
+        if foot.is_stepping() {
+            stepping.insert(root, foot.group);
+        }
+        feet.push((foot_ent, root));
+    }
+
+    for (foot_ent, root) in feet {
+        let Ok((root_tr, parent, driver)) = q_root.get(root) else {
+            continue;
+        };
+        let Ok((_, mut foot, ik_target)) = q_foot.get_mut(foot_ent) else {
+            continue;
+        };
+        let global = base_transform(parent, &q_gtr).mul_transform(*root_tr);
+        let velocity = driver.map_or(Vec3::ZERO, |d| d.desired_velocity(global.translation));
+        // step ahead of the motion, so the foot lands close to its rest position
+        let home = global.transform_point(foot.rest) + velocity * foot.step_duration / 2.;
+        let rig = rig_entities(root, &q_children);
+        let home = ground_point(&spatial_query, home, GROUND_REACH, &rig).unwrap_or(home);
+
+        let position = if let Some(mut step) = foot.step {
+            step.elapsed += dt;
+            let t = step.elapsed / foot.step_duration;
+            if t >= 1. {
+                foot.planted = Some(step.to);
+                foot.step = None;
+                step.to
+            } else {
+                foot.step = Some(step);
+                step_arc(step.from, step.to, foot.step_height, t)
+            }
+        } else {
+            let planted = *foot.planted.get_or_insert(home);
+            let free = !stepping
+                .get(&root)
+                .is_some_and(|group| *group != foot.group);
+            if free && planted.distance(home) > foot.step_distance {
+                stepping.insert(root, foot.group);
+                foot.step = Some(FootStep {
+                    from: planted,
+                    to: home,
+                    elapsed: 0.,
+                });
+            }
+            planted
+        };
+
+        if let Some(mut ik_target) = ik_target {
+            ik_target.target = position;
+        } else {
+            cmd.entity(foot_ent).insert(IkTarget::new(position));
+        }
+    }
+}
@@ -1,3 +1,9 @@
+pub mod fk;
 pub mod fox;
+pub mod ik;
 pub mod joint;
+pub mod locomotion;
+pub mod reach;
 pub mod rig;
+pub mod rig_def;
+pub mod sequence;
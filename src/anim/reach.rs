@@ -0,0 +1,149 @@
+use bevy::prelude::*;
+use rand::prelude::*;
+
+use crate::ui::selection::{Selected, SelectionUiState};
+
+use super::{
+    fk::{FkJoint, FkModel, RigFk},
+    rig::{KiEffector, KiRoot},
+    sequence::JointTarget,
+};
+
+pub struct ReachPlugin;
+
+impl Plugin for ReachPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (update_effector_reach, draw_effector_reach).chain());
+    }
+}
+
+/// Number of poses sampled for a reach cloud.
+const REACH_SAMPLES: usize = 2000;
+
+/// Points an effector reaches, in the space of its `KiRoot`.
+#[derive(Component)]
+pub struct EffectorReach {
+    pub root: Entity,
+    pub points: Vec<Vec3>,
+}
+
+/// Positions of `effector` at random poses within the limits of the joints above it.
+pub fn sample_reach(
+    model: &FkModel,
+    effector: Entity,
+    samples: usize,
+    rng: &mut impl Rng,
+) -> Vec<Vec3> {
+    let Some(mut node) = model.nodes.iter().position(|n| n.entity == effector) else {
+        return vec![];
+    };
+    let mut joints = vec![];
+    while let Some(parent) = model.nodes[node].parent {
+        node = parent;
+        if let Some(joint) = &model.nodes[node].joint {
+            joints.push((model.nodes[node].entity, joint));
+        }
+    }
+
+    let mut pose = model.current_pose();
+    (0..samples)
+        .filter_map(|_| {
+            for (entity, joint) in &joints {
+                pose.insert(*entity, random_joint_target(joint, rng));
+            }
+            model.solve(&pose).effector_position(effector)
+        })
+        .collect()
+}
+
+fn random_joint_target(joint: &FkJoint, rng: &mut impl Rng) -> JointTarget {
+    match joint {
+        FkJoint::Revolute(joint) => {
+            JointTarget::Revolute(sample(rng, joint.min_angle, joint.max_angle))
+        }
+        FkJoint::Spherical(joint) => {
+            let axis = joint.start_rot * Vec3::Y;
+            let swing_axis = Quat::from_axis_angle(axis, rng.gen_range(0.0..std::f32::consts::TAU))
+                * axis.any_orthonormal_vector();
+            // uniform over the cone's cap rather than over the swing angle
+            let cos_swing = rng.gen_range(joint.swing_limit.cos()..=1.);
+            let swing = Quat::from_axis_angle(swing_axis, cos_swing.clamp(-1., 1.).acos());
+            let twist = Quat::from_axis_angle(axis, sample(rng, joint.twist_min, joint.twist_max));
+            JointTarget::Spherical(swing * twist)
+        }
+    }
+}
+
+/// Uniform in `a..=b`, tolerating inverted limits.
+fn sample(rng: &mut impl Rng, a: f32, b: f32) -> f32 {
+    rng.gen_range(a.min(b)..=a.max(b))
+}
+
+fn root_of(
+    entity: Entity,
+    q_parent: &Query<&Parent>,
+    q_root: &Query<&GlobalTransform, With<KiRoot>>,
+) -> Option<Entity> {
+    q_parent
+        .iter_ancestors(entity)
+        .find(|e| q_root.contains(*e))
+}
+
+fn update_effector_reach(
+    selection: Res<SelectionUiState>,
+    rig_fk: RigFk,
+    q_parent: Query<&Parent>,
+    q_root: Query<&GlobalTransform, With<KiRoot>>,
+    q_new: Query<Entity, (With<KiEffector>, With<Selected>, Without<EffectorReach>)>,
+    q_old: Query<(Entity, Has<Selected>), With<EffectorReach>>,
+    mut cmd: Commands,
+) {
+    for (entity, selected) in &q_old {
+        if !selection.show_effector_reach || !selected {
+            cmd.entity(entity).remove::<EffectorReach>();
+        }
+    }
+    if !selection.show_effector_reach {
+        return;
+    }
+    let mut rng = rand::thread_rng();
+    for effector in &q_new {
+        let Some(root) = root_of(effector, &q_parent, &q_root) else {
+            continue;
+        };
+        let Ok(root_gtr) = q_root.get(root) else {
+            continue;
+        };
+        let model = rig_fk.model(root);
+        let to_root = root_gtr.compute_matrix().inverse();
+        let points = sample_reach(&model, effector, REACH_SAMPLES, &mut rng)
+            .into_iter()
+            .map(|p| to_root.transform_point3(p))
+            .collect();
+        cmd.entity(effector).insert(EffectorReach { root, points });
+    }
+}
+
+fn draw_effector_reach(
+    mut gizmos: Gizmos,
+    q_reach: Query<(&EffectorReach, &GlobalTransform)>,
+    q_root: Query<&GlobalTransform, With<KiRoot>>,
+) {
+    let size = 0.01;
+    for (reach, effector_gtr) in &q_reach {
+        let Ok(root_gtr) = q_root.get(reach.root) else {
+            continue;
+        };
+        for point in &reach.points {
+            let p = root_gtr.transform_point(*point);
+            gizmos.line(p - Vec3::X * size, p + Vec3::X * size, Color::CYAN);
+            gizmos.line(p - Vec3::Z * size, p + Vec3::Z * size, Color::CYAN);
+        }
+        gizmos.sphere(
+            effector_gtr.translation(),
+            Quat::IDENTITY,
+            0.05,
+            Color::ORANGE_RED,
+        );
+    }
+}
@@ -1,27 +1,108 @@
-use bevy::prelude::*;
+use std::{collections::VecDeque, f32::consts::PI};
+
+use bevy::{prelude::*, transform::TransformSystem};
 
 pub struct RigPlugin;
 
 impl Plugin for RigPlugin {
     fn build(&self, app: &mut App) {
-        app.register_type::<KiRoot>()
-            .register_type::<KiRootDriver>()
-            .register_type::<KiLoop>()
-            .register_type::<KiBone>()
-            .register_type::<KiEffector>()
-            .register_type::<KiRevoluteJoint>()
-            .register_type::<KiSphericalJoint>();
+        app.configure_sets(
+            PostUpdate,
+            (RigSystem::Commands, RigSystem::Solve)
+                .chain()
+                .before(TransformSystem::TransformPropagate),
+        )
+        .register_type::<KiRoot>()
+        .register_type::<KiRootDriver>()
+        .register_type::<KiLoop>()
+        .register_type::<KiBone>()
+        .register_type::<KiEffector>()
+        .register_type::<KiRevoluteJoint>()
+        .register_type::<KiSphericalJoint>();
     }
 }
 
+/// Rig systems run in `PostUpdate`, before transform propagation.
+/// Joint commands are applied first, then the solvers adjust the remaining free joints.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RigSystem {
+    Commands,
+    Solve,
+}
+
 #[derive(Component, Reflect)]
 pub struct KiRoot;
 
-#[derive(Component, Reflect)]
-pub struct KiRootDriver;
+/// Moves its `KiRoot` over the ground, along `waypoints` or else with a constant `velocity`.
+#[derive(Component, Reflect, Default)]
+pub struct KiRootDriver {
+    /// World space velocity used when there are no waypoints left.
+    pub velocity: Vec3,
+    /// World space points visited in order. Reached ones are removed.
+    pub waypoints: VecDeque<Vec3>,
+    /// Meters per second along the waypoints.
+    pub speed: f32,
+    /// Radians per second when turning to face the direction of motion.
+    pub turn_speed: f32,
+    /// If set, the root is kept at this height above the ground below it.
+    pub ride_height: Option<f32>,
+}
+
+impl KiRootDriver {
+    pub fn with_velocity(velocity: Vec3) -> Self {
+        Self {
+            velocity,
+            turn_speed: PI,
+            ..default()
+        }
+    }
+
+    pub fn with_waypoints(waypoints: impl IntoIterator<Item = Vec3>, speed: f32) -> Self {
+        Self {
+            waypoints: waypoints.into_iter().collect(),
+            speed,
+            turn_speed: PI,
+            ..default()
+        }
+    }
+
+    /// Horizontal velocity wanted at `position`.
+    pub fn desired_velocity(&self, position: Vec3) -> Vec3 {
+        let velocity = match self.waypoints.front() {
+            Some(waypoint) => *waypoint - position,
+            None => self.velocity,
+        };
+        let velocity = Vec3::new(velocity.x, 0., velocity.z);
+        if self.waypoints.is_empty() {
+            velocity
+        } else {
+            velocity.normalize_or_zero() * self.speed
+        }
+    }
+}
 
+/// Closes a kinematic loop: this entity and `other` must stay joined.
+///
+/// Both must be below the same `KiRoot`. The joints they share and the joints executing
+/// a joint command are left untouched, the others are solved so that both points meet.
 #[derive(Component, Reflect)]
-pub struct KiLoop;
+pub struct KiLoop {
+    pub other: Entity,
+    /// Max number of solver passes per frame.
+    pub iterations: u32,
+    /// Distance under which the loop counts as closed.
+    pub tolerance: f32,
+}
+
+impl KiLoop {
+    pub fn new(other: Entity) -> Self {
+        Self {
+            other,
+            iterations: 10,
+            tolerance: 0.01,
+        }
+    }
+}
 
 #[derive(Component, Reflect)]
 pub struct KiBone {
@@ -43,14 +124,34 @@ pub enum KiJointType {
     Spherical,
 }
 
-#[derive(Component, Reflect)]
+#[derive(Component, Reflect, Clone)]
 pub struct KiRevoluteJoint {
     pub length: f32,
     pub start_dir: Vec3,
+    /// Lower limit of the angle with `start_dir`, about the joint's right axis.
+    pub min_angle: f32,
+    /// Upper limit of the angle with `start_dir`, about the joint's right axis.
+    pub max_angle: f32,
     pub show_mesh: bool,
 }
 
 impl KiRevoluteJoint {
+    pub fn new(length: f32, start_dir: Vec3) -> Self {
+        Self {
+            length,
+            start_dir,
+            min_angle: -PI + 0.01,
+            max_angle: PI,
+            show_mesh: false,
+        }
+    }
+
+    pub fn with_limits(mut self, min_angle: f32, max_angle: f32) -> Self {
+        self.min_angle = min_angle;
+        self.max_angle = max_angle;
+        self
+    }
+
     pub fn get_angle(&self, tr: &Transform) -> f32 {
         let sign = {
             let dir = self.start_dir.cross(tr.up());
@@ -62,10 +163,108 @@ impl KiRevoluteJoint {
         };
         sign * self.start_dir.angle_between(tr.up())
     }
+
+    pub fn clamp_angle(&self, angle: f32) -> f32 {
+        angle.clamp(self.min_angle, self.max_angle)
+    }
+
+    /// Rotates the joint about its right axis so that `get_angle` returns `angle`.
+    pub fn set_angle(&self, tr: &mut Transform, angle: f32) {
+        let axis = tr.right();
+        tr.rotate_axis(axis, angle - self.get_angle(tr));
+    }
+
+    /// Rotates the joint about its right axis back inside the limits, if needed.
+    pub fn clamp_transform(&self, tr: &mut Transform) {
+        let angle = self.get_angle(tr);
+        let clamped = self.clamp_angle(angle);
+        if clamped != angle {
+            let axis = tr.right();
+            tr.rotate_axis(axis, clamped - angle);
+        }
+    }
 }
 
-#[derive(Component, Reflect)]
+/// The rotation of the joint is `rot * start_rot`, where `rot` is what joint commands target.
+/// Limits are expressed as a swing-twist decomposition of `rot` about the rest bone axis
+/// (`start_rot * Vec3::Y`).
+#[derive(Component, Reflect, Clone)]
 pub struct KiSphericalJoint {
     pub start_rot: Quat,
+    /// Half angle of the cone the bone may swing in.
+    pub swing_limit: f32,
+    /// Lower limit of the twist about the bone axis.
+    pub twist_min: f32,
+    /// Upper limit of the twist about the bone axis.
+    pub twist_max: f32,
     pub show_mesh: bool,
 }
+
+impl KiSphericalJoint {
+    pub fn new(start_rot: Quat) -> Self {
+        Self {
+            start_rot,
+            swing_limit: PI,
+            twist_min: -PI,
+            twist_max: PI,
+            show_mesh: false,
+        }
+    }
+
+    pub fn with_limits(mut self, swing_limit: f32, twist_min: f32, twist_max: f32) -> Self {
+        self.swing_limit = swing_limit;
+        self.twist_min = twist_min;
+        self.twist_max = twist_max;
+        self
+    }
+
+    pub fn get_rotation(&self, tr: &Transform) -> Quat {
+        (tr.rotation * self.start_rot.inverse()).normalize()
+    }
+
+    /// Inverse of `get_rotation`.
+    pub fn set_rotation(&self, tr: &mut Transform, rot: Quat) {
+        tr.rotation = (rot * self.start_rot).normalize();
+    }
+
+    pub fn clamp_rotation(&self, rot: Quat) -> Quat {
+        let axis = self.start_rot * Vec3::Y;
+        let (swing, twist) = swing_twist(rot, axis);
+
+        let (twist_axis, twist_angle) = twist.to_axis_angle();
+        let mut twist_angle = twist_angle * twist_axis.dot(axis).signum();
+        if twist_angle > PI {
+            twist_angle -= 2. * PI;
+        } else if twist_angle < -PI {
+            twist_angle += 2. * PI;
+        }
+        let twist = Quat::from_axis_angle(axis, twist_angle.clamp(self.twist_min, self.twist_max));
+
+        let (swing_axis, swing_angle) = swing.to_axis_angle();
+        let swing = if swing_angle > self.swing_limit {
+            Quat::from_axis_angle(swing_axis, self.swing_limit)
+        } else {
+            swing
+        };
+
+        (swing * twist).normalize()
+    }
+
+    /// Rotates the joint back inside the limits, if needed.
+    pub fn clamp_transform(&self, tr: &mut Transform) {
+        tr.rotation = (self.clamp_rotation(self.get_rotation(tr)) * self.start_rot).normalize();
+    }
+}
+
+/// Splits `rot` into a swing (perpendicular to `axis`) and a twist (about `axis`),
+/// such that `rot = swing * twist`.
+fn swing_twist(rot: Quat, axis: Vec3) -> (Quat, Quat) {
+    let proj = axis * Vec3::new(rot.x, rot.y, rot.z).dot(axis);
+    let twist = Quat::from_xyzw(proj.x, proj.y, proj.z, rot.w);
+    let twist = if twist.length_squared() < 1e-8 {
+        Quat::IDENTITY
+    } else {
+        twist.normalize()
+    };
+    (rot * twist.conjugate(), twist)
+}
@@ -0,0 +1,538 @@
+use std::{fmt, fs, path::PathBuf};
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    ecs::system::{EntityCommand, EntityCommands},
+    prelude::*,
+    utils::BoxedFuture,
+};
+use bevy_xpbd_3d::prelude::*;
+use futures_lite::AsyncReadExt;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ai::terrain::Terrain,
+    camera::MainCamera,
+    ui::{
+        basic_materials::BasicMaterials,
+        selection::{Layer, Selectable},
+        side_panel::{SidePanel, UiMode},
+    },
+};
+
+use super::rig::{KiBone, KiEffector, KiRevoluteJoint, KiRoot, KiSphericalJoint};
+
+pub struct RigDefPlugin;
+
+impl Plugin for RigDefPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<RigDef>()
+            .init_asset_loader::<RigDefLoader>()
+            .register_type::<RigSpawner>()
+            .register_type::<RigMesh>()
+            .add_systems(Update, (add_rig, spawn_rigs));
+    }
+}
+
+/// A rig hierarchy, loaded from `*.rig.ron` files.
+#[derive(Asset, TypePath, Serialize, Deserialize, Clone, Debug)]
+pub struct RigDef {
+    /// Spawned with a `KiRoot`.
+    pub root: RigNodeDef,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct RigNodeDef {
+    pub name: Option<String>,
+    /// Relative to the parent node.
+    pub translation: Vec3,
+    /// Relative to the parent node.
+    pub rotation: Quat,
+    pub joint: Option<JointDef>,
+    /// Length of the `KiBone`.
+    pub bone: Option<f32>,
+    pub effector: bool,
+    pub mesh: Option<MeshDef>,
+    pub children: Vec<RigNodeDef>,
+}
+
+/// Limits left out use the joint defaults.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum JointDef {
+    Revolute {
+        length: f32,
+        start_dir: Vec3,
+        #[serde(default)]
+        min_angle: Option<f32>,
+        #[serde(default)]
+        max_angle: Option<f32>,
+        #[serde(default)]
+        show_mesh: bool,
+    },
+    Spherical {
+        #[serde(default)]
+        start_rot: Quat,
+        #[serde(default)]
+        swing_limit: Option<f32>,
+        #[serde(default)]
+        twist_min: Option<f32>,
+        #[serde(default)]
+        twist_max: Option<f32>,
+        #[serde(default)]
+        show_mesh: bool,
+    },
+}
+
+/// Visual mesh of a node, also used as its selection collider.
+#[derive(Serialize, Deserialize, Clone, Debug, Reflect)]
+pub struct MeshDef {
+    pub shape: ShapeDef,
+    /// Relative to the node.
+    #[serde(default)]
+    pub translation: Vec3,
+    /// Relative to the node.
+    #[serde(default)]
+    pub rotation: Quat,
+    /// Linear RGB. Uses the salmon material if left out.
+    #[serde(default)]
+    pub color: Option<[f32; 3]>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Reflect)]
+pub enum ShapeDef {
+    Cuboid(Vec3),
+    Sphere(f32),
+    /// Along the Y axis, `length` excludes the caps.
+    Capsule {
+        radius: f32,
+        length: f32,
+    },
+    /// Along the Y axis.
+    Cylinder {
+        radius: f32,
+        height: f32,
+    },
+}
+
+impl ShapeDef {
+    fn mesh(&self) -> Mesh {
+        match *self {
+            ShapeDef::Cuboid(size) => Mesh::from(shape::Box::new(size.x, size.y, size.z)),
+            ShapeDef::Sphere(radius) => Mesh::from(shape::UVSphere {
+                radius,
+                ..default()
+            }),
+            ShapeDef::Capsule { radius, length } => Mesh::from(shape::Capsule {
+                radius,
+                depth: length,
+                ..default()
+            }),
+            ShapeDef::Cylinder { radius, height } => Mesh::from(shape::Cylinder {
+                radius,
+                height,
+                ..default()
+            }),
+        }
+    }
+
+    fn collider(&self) -> Collider {
+        match *self {
+            ShapeDef::Cuboid(size) => Collider::cuboid(size.x, size.y, size.z),
+            ShapeDef::Sphere(radius) => Collider::ball(radius),
+            ShapeDef::Capsule { radius, length } => Collider::capsule(length, radius),
+            ShapeDef::Cylinder { radius, height } => Collider::cylinder(height, radius),
+        }
+    }
+}
+
+impl From<&KiRevoluteJoint> for JointDef {
+    fn from(joint: &KiRevoluteJoint) -> Self {
+        JointDef::Revolute {
+            length: joint.length,
+            start_dir: joint.start_dir,
+            min_angle: Some(joint.min_angle),
+            max_angle: Some(joint.max_angle),
+            show_mesh: joint.show_mesh,
+        }
+    }
+}
+
+impl From<&KiSphericalJoint> for JointDef {
+    fn from(joint: &KiSphericalJoint) -> Self {
+        JointDef::Spherical {
+            start_rot: joint.start_rot,
+            swing_limit: Some(joint.swing_limit),
+            twist_min: Some(joint.twist_min),
+            twist_max: Some(joint.twist_max),
+            show_mesh: joint.show_mesh,
+        }
+    }
+}
+
+impl RigNodeDef {
+    /// Checks the joint limits of the node & its descendants.
+    fn validate(&self) -> Result<(), RigDefLoaderError> {
+        if let Some(joint) = &self.joint {
+            let (min, max) = joint.limits();
+            if min > max {
+                return Err(RigDefLoaderError::InvertedLimits {
+                    node: self.name.clone().unwrap_or_default(),
+                    min,
+                    max,
+                });
+            }
+        }
+        self.children.iter().try_for_each(RigNodeDef::validate)
+    }
+}
+
+impl JointDef {
+    /// Angle limits of a revolute joint, or twist limits of a spherical one, with defaults.
+    fn limits(&self) -> (f32, f32) {
+        match self {
+            JointDef::Revolute {
+                min_angle,
+                max_angle,
+                ..
+            } => {
+                let joint = KiRevoluteJoint::new(1., Vec3::Y);
+                (
+                    min_angle.unwrap_or(joint.min_angle),
+                    max_angle.unwrap_or(joint.max_angle),
+                )
+            }
+            JointDef::Spherical {
+                twist_min,
+                twist_max,
+                ..
+            } => {
+                let joint = KiSphericalJoint::new(Quat::IDENTITY);
+                (
+                    twist_min.unwrap_or(joint.twist_min),
+                    twist_max.unwrap_or(joint.twist_max),
+                )
+            }
+        }
+    }
+
+    fn insert(&self, cmd: &mut EntityCommands) {
+        match self {
+            JointDef::Revolute {
+                length,
+                start_dir,
+                min_angle,
+                max_angle,
+                show_mesh,
+            } => {
+                let mut joint = KiRevoluteJoint::new(*length, *start_dir);
+                joint.min_angle = min_angle.unwrap_or(joint.min_angle);
+                joint.max_angle = max_angle.unwrap_or(joint.max_angle);
+                joint.show_mesh = *show_mesh;
+                cmd.insert(joint);
+            }
+            JointDef::Spherical {
+                start_rot,
+                swing_limit,
+                twist_min,
+                twist_max,
+                show_mesh,
+            } => {
+                let mut joint = KiSphericalJoint::new(*start_rot);
+                joint.swing_limit = swing_limit.unwrap_or(joint.swing_limit);
+                joint.twist_min = twist_min.unwrap_or(joint.twist_min);
+                joint.twist_max = twist_max.unwrap_or(joint.twist_max);
+                joint.show_mesh = *show_mesh;
+                cmd.insert(joint);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RigDefLoaderError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+    /// A joint's minimum limit is above its maximum.
+    InvertedLimits {
+        node: String,
+        min: f32,
+        max: f32,
+    },
+}
+
+impl fmt::Display for RigDefLoaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RigDefLoaderError::Io(err) => write!(f, "could not read rig: {err}"),
+            RigDefLoaderError::Ron(err) => write!(f, "could not parse rig: {err}"),
+            RigDefLoaderError::InvertedLimits { node, min, max } => {
+                write!(
+                    f,
+                    "joint limits of node '{node}' are inverted: {min} > {max}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for RigDefLoaderError {}
+
+impl From<std::io::Error> for RigDefLoaderError {
+    fn from(err: std::io::Error) -> Self {
+        RigDefLoaderError::Io(err)
+    }
+}
+
+impl From<ron::error::SpannedError> for RigDefLoaderError {
+    fn from(err: ron::error::SpannedError) -> Self {
+        RigDefLoaderError::Ron(err)
+    }
+}
+
+#[derive(Default)]
+pub struct RigDefLoader;
+
+impl AssetLoader for RigDefLoader {
+    type Asset = RigDef;
+    type Settings = ();
+    type Error = RigDefLoaderError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<RigDef, RigDefLoaderError>> {
+        Box::pin(async move {
+            let mut bytes = vec![];
+            reader.read_to_end(&mut bytes).await?;
+            let rig: RigDef = ron::de::from_bytes(&bytes)?;
+            rig.root.validate()?;
+            Ok(rig)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["rig.ron"]
+    }
+}
+
+/// Spawns a `RigDef` as its child, and respawns it whenever the asset changes.
+#[derive(Component, Reflect)]
+pub struct RigSpawner {
+    pub rig: Handle<RigDef>,
+    root: Option<Entity>,
+}
+
+impl RigSpawner {
+    pub fn new(rig: Handle<RigDef>) -> Self {
+        Self { rig, root: None }
+    }
+
+    pub fn root(&self) -> Option<Entity> {
+        self.root
+    }
+}
+
+/// The `MeshDef` a rig mesh was spawned from, kept for exporting.
+#[derive(Component, Reflect)]
+pub struct RigMesh(pub MeshDef);
+
+fn spawn_rigs(
+    mut ev_asset: EventReader<AssetEvent<RigDef>>,
+    rigs: Res<Assets<RigDef>>,
+    basic_materials: Res<BasicMaterials>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut q_spawner: Query<(Entity, &mut RigSpawner)>,
+    mut cmd: Commands,
+) {
+    let modified: Vec<_> = ev_asset
+        .read()
+        .filter_map(|ev| match ev {
+            AssetEvent::Modified { id } => Some(*id),
+            _ => None,
+        })
+        .collect();
+    for (entity, mut spawner) in &mut q_spawner {
+        if spawner.root.is_some() && !modified.contains(&spawner.rig.id()) {
+            continue;
+        }
+        let Some(rig) = rigs.get(&spawner.rig) else {
+            continue;
+        };
+        if let Some(root) = spawner.root {
+            cmd.entity(root).despawn_recursive();
+        }
+        let root = spawn_node(
+            &rig.root,
+            &mut cmd,
+            &mut meshes,
+            &mut materials,
+            &basic_materials.salmon,
+        );
+        cmd.entity(root).insert(KiRoot);
+        cmd.entity(entity).add_child(root);
+        spawner.root = Some(root);
+    }
+}
+
+fn spawn_node(
+    node: &RigNodeDef,
+    cmd: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    default_material: &Handle<StandardMaterial>,
+) -> Entity {
+    let entity = cmd
+        .spawn(SpatialBundle::from_transform(
+            Transform::from_translation(node.translation).with_rotation(node.rotation),
+        ))
+        .id();
+    let mut node_cmd = cmd.entity(entity);
+    if let Some(name) = &node.name {
+        node_cmd.insert(Name::new(name.clone()));
+    }
+    if let Some(joint) = &node.joint {
+        joint.insert(&mut node_cmd);
+    }
+    if let Some(length) = node.bone {
+        node_cmd.insert(KiBone::new(length));
+    }
+    if node.effector {
+        node_cmd.insert(KiEffector);
+    }
+
+    if let Some(mesh_def) = &node.mesh {
+        let material = mesh_def
+            .color
+            .map_or(default_material.clone(), |[r, g, b]| {
+                materials.add(StandardMaterial::from(Color::rgb_linear(r, g, b)))
+            });
+        let mesh = cmd
+            .spawn((
+                PbrBundle {
+                    transform: Transform::from_translation(mesh_def.translation)
+                        .with_rotation(mesh_def.rotation),
+                    mesh: meshes.add(mesh_def.shape.mesh()),
+                    material,
+                    ..default()
+                },
+                mesh_def.shape.collider(),
+                CollisionLayers::new([Layer::Object], [Layer::Object]),
+                RigMesh(mesh_def.clone()),
+            ))
+            .id();
+        cmd.entity(mesh).insert(Selectable::new(entity, Some(mesh)));
+        cmd.entity(entity).add_child(mesh);
+    }
+
+    for child in &node.children {
+        let child = spawn_node(child, cmd, meshes, materials, default_material);
+        cmd.entity(entity).add_child(child);
+    }
+    entity
+}
+
+/// Writes the rig below a `KiRoot` to a `*.rig.ron` file.
+pub struct ExportRig {
+    pub path: PathBuf,
+}
+
+impl EntityCommand for ExportRig {
+    fn apply(self, id: Entity, world: &mut World) {
+        let rig = RigDef {
+            root: export_node(world, id),
+        };
+        let result = ron::ser::to_string_pretty(&rig, ron::ser::PrettyConfig::default())
+            .map_err(|err| err.to_string())
+            .and_then(|ron| {
+                if let Some(dir) = self.path.parent() {
+                    fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+                }
+                fs::write(&self.path, ron).map_err(|err| err.to_string())
+            });
+        match result {
+            Ok(()) => info!("rig exported to {}", self.path.display()),
+            Err(err) => error!("could not export rig to {}: {err}", self.path.display()),
+        }
+    }
+}
+
+fn export_node(world: &World, entity: Entity) -> RigNodeDef {
+    let tr = world.get::<Transform>(entity).copied().unwrap_or_default();
+    let joint = if let Some(joint) = world.get::<KiRevoluteJoint>(entity) {
+        Some(joint.into())
+    } else {
+        world.get::<KiSphericalJoint>(entity).map(JointDef::from)
+    };
+    let children = world
+        .get::<Children>(entity)
+        .map(|c| c.to_vec())
+        .unwrap_or_default();
+    RigNodeDef {
+        name: world.get::<Name>(entity).map(|n| n.to_string()),
+        translation: tr.translation,
+        rotation: tr.rotation,
+        joint,
+        bone: world.get::<KiBone>(entity).map(|b| b.length),
+        effector: world.get::<KiEffector>(entity).is_some(),
+        mesh: children
+            .iter()
+            .find_map(|c| world.get::<RigMesh>(*c))
+            .map(|m| m.0.clone()),
+        children: children
+            .iter()
+            .filter(|c| is_rig_node(world, **c))
+            .map(|c| export_node(world, *c))
+            .collect(),
+    }
+}
+
+/// Whether the entity is part of the kinematic tree, rather than a mesh or gizmo.
+fn is_rig_node(world: &World, entity: Entity) -> bool {
+    let entity_ref = world.entity(entity);
+    entity_ref.contains::<KiBone>()
+        || entity_ref.contains::<KiEffector>()
+        || entity_ref.contains::<KiRevoluteJoint>()
+        || entity_ref.contains::<KiSphericalJoint>()
+        || world
+            .get::<Children>(entity)
+            .is_some_and(|c| c.iter().any(|c| is_rig_node(world, *c)))
+}
+
+fn add_rig(
+    mouse: Res<Input<MouseButton>>,
+    asset_server: Res<AssetServer>,
+    spatial_query: SpatialQuery,
+    panel: Res<SidePanel>,
+    terrain: Res<Terrain>,
+    q_camera: Query<&MainCamera>,
+    mut cmd: Commands,
+) {
+    if panel.mode != UiMode::AddRig || panel.mouse_over || !mouse.just_pressed(MouseButton::Left) {
+        return;
+    };
+    let Ok(Some(ray)) = q_camera.get_single().map(|c| c.mouse_ray) else {
+        return;
+    };
+    let Some(hit) = spatial_query.cast_ray(
+        ray.origin,
+        ray.direction,
+        1000.,
+        false,
+        SpatialQueryFilter::new().with_masks([Layer::Object]),
+    ) else {
+        return;
+    };
+    if !terrain.is_ground(hit.entity) {
+        return;
+    }
+    let pos = ray.origin + hit.time_of_impact * ray.direction;
+    cmd.spawn((
+        Name::new("Rig"),
+        SpatialBundle::from_transform(Transform::from_translation(pos)),
+        RigSpawner::new(asset_server.load("rigs/arm.rig.ron")),
+    ));
+}
@@ -0,0 +1,322 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    ecs::system::{EntityCommand, EntityCommands},
+    prelude::*,
+};
+
+use super::{
+    joint::{MotionProfile, RevoluteJointCommand, SphericalJointCommand},
+    rig::{KiRevoluteJoint, KiSphericalJoint},
+};
+
+pub struct SequencePlugin;
+
+impl Plugin for SequencePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<JointCommandQueue>()
+            .register_type::<RigTimeline>()
+            .add_systems(Update, (advance_joint_queues, play_rig_timelines));
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Reflect)]
+pub enum JointTarget {
+    /// Angle of a `KiRevoluteJoint`.
+    Revolute(f32),
+    /// Rotation of a `KiSphericalJoint`, relative to its `start_rot`.
+    Spherical(Quat),
+}
+
+impl JointTarget {
+    fn command(
+        &self,
+        speed: f32,
+        profile: MotionProfile,
+        stop_at_collisions: bool,
+        cmd: &mut EntityCommands,
+    ) {
+        match *self {
+            JointTarget::Revolute(angle) => {
+                cmd.insert(
+                    RevoluteJointCommand::new(angle, speed, stop_at_collisions)
+                        .with_profile(profile),
+                );
+            }
+            JointTarget::Spherical(rot) => {
+                cmd.insert(
+                    SphericalJointCommand::new(rot, speed, stop_at_collisions)
+                        .with_profile(profile),
+                );
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Reflect)]
+pub enum JointStep {
+    /// Move to `target` with a cruise `speed` in radians per second.
+    Move {
+        target: JointTarget,
+        speed: f32,
+        profile: MotionProfile,
+        stop_at_collisions: bool,
+    },
+    /// Hold the current pose for some seconds.
+    Wait(f32),
+}
+
+/// Joint commands played in order on a single joint.
+///
+/// A step starts once the joint has no `RevoluteJointCommand` or `SphericalJointCommand` left.
+#[derive(Component, Default, Reflect)]
+pub struct JointCommandQueue {
+    pub steps: VecDeque<JointStep>,
+    /// Finished steps are pushed back to the end of the queue.
+    pub looping: bool,
+    wait: f32,
+}
+
+impl JointCommandQueue {
+    pub fn new(steps: impl IntoIterator<Item = JointStep>) -> Self {
+        Self {
+            steps: steps.into_iter().collect(),
+            ..default()
+        }
+    }
+
+    pub fn looping(mut self) -> Self {
+        self.looping = true;
+        self
+    }
+}
+
+/// Appends a step to the joint's `JointCommandQueue`, inserting one if missing.
+pub struct QueueJointStep(pub JointStep);
+
+impl EntityCommand for QueueJointStep {
+    fn apply(self, id: Entity, world: &mut World) {
+        let mut entity = world.entity_mut(id);
+        if let Some(mut queue) = entity.get_mut::<JointCommandQueue>() {
+            queue.steps.push_back(self.0);
+        } else {
+            entity.insert(JointCommandQueue::new([self.0]));
+        }
+    }
+}
+
+fn advance_joint_queues(
+    time: Res<Time>,
+    mut q_queue: Query<(
+        Entity,
+        &mut JointCommandQueue,
+        Has<RevoluteJointCommand>,
+        Has<SphericalJointCommand>,
+    )>,
+    mut cmd: Commands,
+) {
+    for (entity, mut queue, revolute_cmd, spherical_cmd) in &mut q_queue {
+        if revolute_cmd || spherical_cmd {
+            continue;
+        }
+        if queue.wait > 0. {
+            queue.wait -= time.delta_seconds();
+            continue;
+        }
+        let Some(step) = queue.steps.pop_front() else {
+            continue;
+        };
+        match &step {
+            JointStep::Move {
+                target,
+                speed,
+                profile,
+                stop_at_collisions,
+            } => {
+                target.command(
+                    *speed,
+                    *profile,
+                    *stop_at_collisions,
+                    &mut cmd.entity(entity),
+                );
+            }
+            JointStep::Wait(secs) => queue.wait = *secs,
+        }
+        if queue.looping {
+            queue.steps.push_back(step);
+        }
+    }
+}
+
+/// A pose of several joints of a rig, reached by all of them at the same time.
+#[derive(Clone, Debug, Default, Reflect)]
+pub struct RigKeyframe {
+    pub targets: Vec<(Entity, JointTarget)>,
+    /// Seconds needed to reach the pose. Joint speeds are derived from it.
+    pub duration: f32,
+    pub profile: MotionProfile,
+    /// Seconds to hold the pose once reached.
+    pub hold: f32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Reflect)]
+enum TimelineState {
+    #[default]
+    Start,
+    Moving,
+    Holding,
+}
+
+/// Keyframed poses of a `KiRoot`'s joints, played in order.
+#[derive(Component, Default, Reflect)]
+pub struct RigTimeline {
+    pub keyframes: Vec<RigKeyframe>,
+    /// Starts over from the first keyframe after the last one.
+    pub looping: bool,
+    pub playing: bool,
+    current: usize,
+    state: TimelineState,
+    elapsed: f32,
+}
+
+impl RigTimeline {
+    pub fn new(keyframes: Vec<RigKeyframe>, looping: bool) -> Self {
+        Self {
+            keyframes,
+            looping,
+            playing: true,
+            ..default()
+        }
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn play(&mut self) {
+        self.playing = true;
+        self.current = 0;
+        self.state = TimelineState::Start;
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = false;
+    }
+}
+
+fn play_rig_timelines(
+    time: Res<Time>,
+    mut q_timeline: Query<&mut RigTimeline>,
+    q_revolute: Query<(&KiRevoluteJoint, &Transform, Has<RevoluteJointCommand>)>,
+    q_spherical: Query<(&KiSphericalJoint, &Transform, Has<SphericalJointCommand>)>,
+    mut cmd: Commands,
+) {
+    for mut timeline in &mut q_timeline {
+        if !timeline.playing || timeline.keyframes.is_empty() {
+            continue;
+        }
+        if timeline.current >= timeline.keyframes.len() {
+            timeline.current = 0;
+        }
+        let timeline = &mut *timeline;
+        let keyframe = &timeline.keyframes[timeline.current];
+
+        match timeline.state {
+            TimelineState::Start => {
+                for (joint_ent, target) in &keyframe.targets {
+                    let distance = match target {
+                        JointTarget::Revolute(angle) => {
+                            let Ok((joint, tr, _)) = q_revolute.get(*joint_ent) else {
+                                continue;
+                            };
+                            (joint.clamp_angle(*angle) - joint.get_angle(tr)).abs()
+                        }
+                        JointTarget::Spherical(rot) => {
+                            let Ok((joint, tr, _)) = q_spherical.get(*joint_ent) else {
+                                continue;
+                            };
+                            joint
+                                .get_rotation(tr)
+                                .angle_between(joint.clamp_rotation(*rot))
+                        }
+                    };
+                    let speed = keyframe
+                        .profile
+                        .speed_for_duration(distance, keyframe.duration);
+                    target.command(speed, keyframe.profile, false, &mut cmd.entity(*joint_ent));
+                }
+                timeline.state = TimelineState::Moving;
+            }
+            TimelineState::Moving => {
+                // commands issued at `Start` are only inserted once the commands are applied
+                let moving = keyframe.targets.iter().any(|(joint_ent, _)| {
+                    q_revolute.get(*joint_ent).is_ok_and(|(_, _, c)| c)
+                        || q_spherical.get(*joint_ent).is_ok_and(|(_, _, c)| c)
+                });
+                if !moving {
+                    timeline.state = TimelineState::Holding;
+                    timeline.elapsed = 0.;
+                }
+            }
+            TimelineState::Holding => {
+                timeline.elapsed += time.delta_seconds();
+                if timeline.elapsed >= keyframe.hold {
+                    timeline.current += 1;
+                    timeline.state = TimelineState::Start;
+                    if timeline.current >= timeline.keyframes.len() {
+                        timeline.current = 0;
+                        timeline.playing = timeline.looping;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Appends the current pose of all the joints below a `KiRoot` to its `RigTimeline`.
+pub struct RecordRigKeyframe {
+    pub duration: f32,
+    pub hold: f32,
+    pub profile: MotionProfile,
+}
+
+impl EntityCommand for RecordRigKeyframe {
+    fn apply(self, id: Entity, world: &mut World) {
+        let mut targets = vec![];
+        let mut q_children = world.query::<&Children>();
+        let mut q_joint = world.query::<(
+            &Transform,
+            Option<&KiRevoluteJoint>,
+            Option<&KiSphericalJoint>,
+        )>();
+        let mut stack = vec![id];
+        while let Some(entity) = stack.pop() {
+            if let Ok((tr, revolute, spherical)) = q_joint.get(world, entity) {
+                if let Some(joint) = revolute {
+                    targets.push((entity, JointTarget::Revolute(joint.get_angle(tr))));
+                } else if let Some(joint) = spherical {
+                    targets.push((entity, JointTarget::Spherical(joint.get_rotation(tr))));
+                }
+            }
+            if let Ok(children) = q_children.get(world, entity) {
+                stack.extend(children.iter());
+            }
+        }
+
+        let keyframe = RigKeyframe {
+            targets,
+            duration: self.duration,
+            profile: self.profile,
+            hold: self.hold,
+        };
+        let mut entity = world.entity_mut(id);
+        if let Some(mut timeline) = entity.get_mut::<RigTimeline>() {
+            timeline.keyframes.push(keyframe);
+        } else {
+            entity.insert(RigTimeline {
+                keyframes: vec![keyframe],
+                ..default()
+            });
+        }
+    }
+}
@@ -11,8 +11,15 @@ use bevy_inspector_egui::DefaultInspectorConfigPlugin;
 use bevy_xpbd_3d::prelude::*;
 
 use protos::{
-    ai::{building::BuildingPlugin, swarm::SwarmPlugin, terrain::TerrainPlugin},
-    anim::{fox::FoxPlugin, joint::JointPlugin, rig::RigPlugin},
+    ai::{
+        building::BuildingPlugin, building_edit::BuildingEditPlugin,
+        building_path::BuildingPathPlugin, building_stairs::BuildingStairsPlugin,
+        navmesh::NavMeshPlugin, swarm::SwarmPlugin, terrain::TerrainPlugin,
+    },
+    anim::{
+        fox::FoxPlugin, ik::IkPlugin, joint::JointPlugin, locomotion::LocomotionPlugin,
+        reach::ReachPlugin, rig::RigPlugin, rig_def::RigDefPlugin, sequence::SequencePlugin,
+    },
     camera::MainCameraPlugin,
     light::{MainLightsPlugin, INFINITE_TEMP_COLOR},
     ui::{
@@ -54,13 +61,24 @@ fn main() {
             TransformGizmoPlugin,
             MainLightsPlugin,
             MainCameraPlugin,
-            RigPlugin,
-            JointPlugin,
             TerrainPlugin,
             FoxPlugin,
             BuildingPlugin,
+            BuildingPathPlugin,
+            BuildingEditPlugin,
+            NavMeshPlugin,
+            BuildingStairsPlugin,
             SwarmPlugin,
         ))
+        .add_plugins((
+            RigPlugin,
+            JointPlugin,
+            IkPlugin,
+            SequencePlugin,
+            LocomotionPlugin,
+            RigDefPlugin,
+            ReachPlugin,
+        ))
         .add_systems(Update, exit_system)
         .run();
 }
@@ -0,0 +1,245 @@
+use bevy::{
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology},
+};
+
+/// Grid of height samples, `cell_size` apart on X & Z.
+#[derive(Clone, Debug)]
+pub struct Heightmap {
+    /// Number of cells on X & Z. There is one more sample on each axis.
+    pub cells: UVec2,
+    pub cell_size: f32,
+    /// XZ position of the first sample.
+    pub origin: Vec2,
+    /// Samples, row by row along Z.
+    pub heights: Vec<f32>,
+}
+
+/// Fractal value noise parameters.
+#[derive(Clone, Debug, Reflect)]
+pub struct HeightNoise {
+    pub seed: u32,
+    /// Of the first octave, in waves per unit.
+    pub frequency: f32,
+    pub octaves: u32,
+    /// Maximum height.
+    pub amplitude: f32,
+}
+
+impl Default for HeightNoise {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            frequency: 0.02,
+            octaves: 4,
+            amplitude: 6.,
+        }
+    }
+}
+
+impl HeightNoise {
+    pub fn sample(&self, point: Vec2) -> f32 {
+        let (mut sum, mut norm) = (0., 0.);
+        let (mut frequency, mut weight) = (self.frequency, 1.);
+        for octave in 0..self.octaves {
+            sum += weight * value_noise(self.seed.wrapping_add(octave), point * frequency);
+            norm += weight;
+            frequency *= 2.;
+            weight *= 0.5;
+        }
+        if norm > 0. {
+            self.amplitude * sum / norm
+        } else {
+            0.
+        }
+    }
+}
+
+fn hash(seed: u32, x: i32, z: i32) -> f32 {
+    let mut h = seed ^ (x as u32).wrapping_mul(0x27d4_eb2d) ^ (z as u32).wrapping_mul(0x1656_67b1);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297a_2d39);
+    h ^= h >> 15;
+    h as f32 / u32::MAX as f32
+}
+
+/// Smoothly interpolated random values at integer points, in `0..1`.
+fn value_noise(seed: u32, point: Vec2) -> f32 {
+    let cell = point.floor();
+    let t = point - cell;
+    let t = t * t * (3. - 2. * t);
+    let (x, z) = (cell.x as i32, cell.y as i32);
+    let top = hash(seed, x, z) + (hash(seed, x + 1, z) - hash(seed, x, z)) * t.x;
+    let bottom = hash(seed, x, z + 1) + (hash(seed, x + 1, z + 1) - hash(seed, x, z + 1)) * t.x;
+    top + (bottom - top) * t.y
+}
+
+impl Heightmap {
+    pub fn from_fn(
+        cells: UVec2,
+        cell_size: f32,
+        origin: Vec2,
+        height: impl Fn(UVec2) -> f32,
+    ) -> Self {
+        let heights = (0..=cells.y)
+            .flat_map(|z| (0..=cells.x).map(move |x| UVec2::new(x, z)))
+            .map(height)
+            .collect();
+        Self {
+            cells,
+            cell_size,
+            origin,
+            heights,
+        }
+    }
+
+    /// One sample per pixel, black at 0 & white at `max_height`. Centered on the origin.
+    pub fn from_image(image: &Image, cell_size: f32, max_height: f32) -> Option<Self> {
+        let luma = image.clone().try_into_dynamic().ok()?.to_luma32f();
+        let (width, height) = luma.dimensions();
+        if width < 2 || height < 2 {
+            return None;
+        }
+        let cells = UVec2::new(width - 1, height - 1);
+        Some(Self {
+            cells,
+            cell_size,
+            origin: -(cells / 2).as_vec2() * cell_size,
+            heights: luma.pixels().map(|p| p.0[0] * max_height).collect(),
+        })
+    }
+
+    /// Extent on X & Z.
+    pub fn size(&self) -> Vec2 {
+        self.cells.as_vec2() * self.cell_size
+    }
+
+    fn index(&self, sample: UVec2) -> usize {
+        (sample.y * (self.cells.x + 1) + sample.x) as usize
+    }
+
+    /// Height of a sample, clamped to the grid.
+    pub fn sample(&self, sample: IVec2) -> f32 {
+        let sample = sample.clamp(IVec2::ZERO, self.cells.as_ivec2()).as_uvec2();
+        self.heights[self.index(sample)]
+    }
+
+    /// XZ position of a sample.
+    pub fn position(&self, sample: UVec2) -> Vec2 {
+        self.origin + sample.as_vec2() * self.cell_size
+    }
+
+    /// XZ bounds.
+    pub fn rect(&self) -> Rect {
+        Rect::from_corners(self.origin, self.origin + self.size())
+    }
+
+    /// Interpolated height at an XZ position, clamped to the grid.
+    pub fn height_at(&self, point: Vec2) -> f32 {
+        let grid = (point - self.origin) / self.cell_size;
+        let cell = grid.floor();
+        let t = (grid - cell).clamp(Vec2::ZERO, Vec2::ONE);
+        let cell = cell.as_ivec2();
+        let h = |dx, dz| self.sample(cell + IVec2::new(dx, dz));
+        let top = h(0, 0) + (h(1, 0) - h(0, 0)) * t.x;
+        let bottom = h(0, 1) + (h(1, 1) - h(0, 1)) * t.x;
+        top + (bottom - top) * t.y
+    }
+
+    /// Surface normal of a sample, from central differences.
+    pub fn normal(&self, sample: IVec2) -> Vec3 {
+        let dx = self.sample(sample + IVec2::X) - self.sample(sample - IVec2::X);
+        let dz = self.sample(sample + IVec2::Y) - self.sample(sample - IVec2::Y);
+        Vec3::new(-dx, 2. * self.cell_size, -dz).normalize()
+    }
+
+    /// Interpolated surface normal at an XZ position.
+    pub fn normal_at(&self, point: Vec2) -> Vec3 {
+        let d = self.cell_size;
+        let dx = self.height_at(point + Vec2::X * d) - self.height_at(point - Vec2::X * d);
+        let dz = self.height_at(point + Vec2::Y * d) - self.height_at(point - Vec2::Y * d);
+        Vec3::new(-dx, 2. * d, -dz).normalize()
+    }
+
+    /// XZ center of a chunk of cells.
+    pub fn chunk_center(&self, min: UVec2, cells: UVec2) -> Vec2 {
+        self.position(min) + cells.as_vec2() * self.cell_size / 2.
+    }
+
+    /// Mesh of a chunk of cells, relative to `chunk_center`.
+    ///
+    /// UVs span `uv_rect`, e.g. the whole terrain, so materials line up across chunks.
+    pub fn chunk_mesh(&self, min: UVec2, cells: UVec2, uv_rect: Rect) -> Mesh {
+        let center = self.chunk_center(min, cells);
+        let count = ((cells.x + 1) * (cells.y + 1)) as usize;
+        let mut positions: Vec<[f32; 3]> = Vec::with_capacity(count);
+        let mut normals: Vec<[f32; 3]> = Vec::with_capacity(count);
+        let mut uvs: Vec<[f32; 2]> = Vec::with_capacity(count);
+        for z in 0..=cells.y {
+            for x in 0..=cells.x {
+                let sample = min + UVec2::new(x, z);
+                let xz = self.position(sample) - center;
+                positions.push([xz.x, self.sample(sample.as_ivec2()), xz.y]);
+                normals.push(self.normal(sample.as_ivec2()).into());
+                uvs.push(((xz + center - uv_rect.min) / uv_rect.size()).into());
+            }
+        }
+        let mut indices: Vec<u32> = Vec::with_capacity((cells.x * cells.y * 6) as usize);
+        let row = cells.x + 1;
+        for z in 0..cells.y {
+            for x in 0..cells.x {
+                let i = z * row + x;
+                // counter-clockwise seen from above
+                indices.extend([i, i + row, i + 1, i + 1, i + row, i + row + 1]);
+            }
+        }
+
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.set_indices(Some(Indices::U32(indices)));
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        mesh
+    }
+
+    /// Heights of a chunk of cells, laid out for `Collider::heightfield`: outer along X, inner along Z.
+    pub fn chunk_heights(&self, min: UVec2, cells: UVec2) -> Vec<Vec<f32>> {
+        (0..=cells.x)
+            .map(|x| {
+                (0..=cells.y)
+                    .map(|z| self.sample((min + UVec2::new(x, z)).as_ivec2()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Removes cracks along one side of a chunk, next to a chunk with `ratio` times larger cells.
+    ///
+    /// The samples on that side are moved onto the neighbor's straight edges.
+    pub fn stitch(&mut self, min: UVec2, cells: UVec2, side: IVec2, ratio: u32) {
+        if ratio <= 1 {
+            return;
+        }
+        // first sample & step along the side, & its length
+        let (start, step, len) = match (side.x, side.y) {
+            (1, _) => (min + UVec2::new(cells.x, 0), UVec2::Y, cells.y),
+            (-1, _) => (min, UVec2::Y, cells.y),
+            (_, 1) => (min + UVec2::new(0, cells.y), UVec2::X, cells.x),
+            _ => (min, UVec2::X, cells.x),
+        };
+        for k in 0..=len {
+            let a = k - k % ratio;
+            let b = (a + ratio).min(len);
+            if a == k || a == b {
+                continue;
+            }
+            let h = |i: u32| self.heights[self.index(start + step * i)];
+            let t = (k - a) as f32 / (b - a) as f32;
+            let height = h(a) + (h(b) - h(a)) * t;
+            let i = self.index(start + step * k);
+            self.heights[i] = height;
+        }
+    }
+}
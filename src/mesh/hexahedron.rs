@@ -0,0 +1,89 @@
+use bevy::{
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology},
+};
+
+/// A box with arbitrarily placed corners, e.g. a sheared or tapered slab.
+///
+/// Non-planar faces are split into two flat triangles along the diagonal from their first corner.
+///
+/// Corner `i` is at the min or max of each axis of the equivalent box,
+/// following the bits of `i`: 1 for X, 2 for Y, 4 for Z.
+#[derive(Debug, Clone, Copy)]
+pub struct Hexahedron {
+    pub corners: [Vec3; 8],
+}
+
+impl Hexahedron {
+    pub fn new(corners: [Vec3; 8]) -> Self {
+        Self { corners }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        self.corners.iter().sum::<Vec3>() / 8.
+    }
+}
+
+impl From<Hexahedron> for Mesh {
+    fn from(hex: Hexahedron) -> Self {
+        const FACES: [[usize; 4]; 6] = [
+            [0, 2, 6, 4],
+            [1, 3, 7, 5],
+            [0, 1, 5, 4],
+            [2, 3, 7, 6],
+            [0, 1, 3, 2],
+            [4, 5, 7, 6],
+        ];
+        let center = hex.center();
+        let mut positions: Vec<[f32; 3]> = Vec::with_capacity(36);
+        let mut normals: Vec<[f32; 3]> = Vec::with_capacity(36);
+        let mut uvs: Vec<[f32; 2]> = Vec::with_capacity(36);
+        let mut indices: Vec<u32> = Vec::with_capacity(36);
+        const QUAD_UVS: [[f32; 2]; 4] = [[0., 0.], [1., 0.], [1., 1.], [0., 1.]];
+
+        for face in FACES {
+            let mut quad = face.map(|i| hex.corners[i]);
+            let face_center = quad.iter().sum::<Vec3>() / 4.;
+            let mut normal = (quad[1] - quad[0])
+                .cross(quad[2] - quad[0])
+                .normalize_or_zero();
+            // counter-clockwise seen from outside
+            if normal.dot(face_center - center) < 0. {
+                quad.reverse();
+                normal = -normal;
+            }
+            let first = positions.len() as u32;
+            let size = quad
+                .iter()
+                .map(|c| c.distance(face_center))
+                .fold(0., f32::max);
+            if normal.dot(quad[3] - quad[0]).abs() <= 1e-4 * size {
+                for (corner, uv) in quad.iter().zip(QUAD_UVS) {
+                    positions.push((*corner).into());
+                    normals.push(normal.into());
+                    uvs.push(uv);
+                }
+                indices.extend([0, 1, 2, 0, 2, 3].map(|i| first + i));
+            } else {
+                // not flat, so each triangle gets its own normal
+                for tri in [[0, 1, 2], [0, 2, 3]] {
+                    let [a, b, c] = tri.map(|i| quad[i]);
+                    let normal = (b - a).cross(c - a).normalize_or_zero();
+                    for i in tri {
+                        positions.push(quad[i].into());
+                        normals.push(normal.into());
+                        uvs.push(QUAD_UVS[i]);
+                    }
+                }
+                indices.extend((0..6).map(|i| first + i));
+            }
+        }
+
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.set_indices(Some(Indices::U32(indices)));
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        mesh
+    }
+}
@@ -1 +1,4 @@
 pub mod cone;
+pub mod heightmap;
+pub mod hexahedron;
+pub mod sector;
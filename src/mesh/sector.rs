@@ -0,0 +1,73 @@
+use bevy::{
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology},
+};
+
+/// A flat circle sector in the YZ plane, swept from `start_angle` to `end_angle`
+/// about the X axis, starting from +Y.
+#[derive(Debug, Clone, Copy)]
+pub struct Sector {
+    pub radius: f32,
+    pub start_angle: f32,
+    pub end_angle: f32,
+    pub subdivisions: usize,
+}
+
+impl Sector {
+    pub fn new(radius: f32, start_angle: f32, end_angle: f32, subdivisions: usize) -> Self {
+        Self {
+            radius,
+            start_angle,
+            end_angle,
+            subdivisions,
+        }
+    }
+}
+
+impl Default for Sector {
+    fn default() -> Self {
+        Sector {
+            radius: 1.0,
+            start_angle: -std::f32::consts::FRAC_PI_2,
+            end_angle: std::f32::consts::FRAC_PI_2,
+            subdivisions: 32,
+        }
+    }
+}
+
+impl From<Sector> for Mesh {
+    fn from(sector: Sector) -> Self {
+        let n_vertices = sector.subdivisions + 2;
+        let mut positions: Vec<[f32; 3]> = Vec::with_capacity(n_vertices);
+        let mut normals: Vec<[f32; 3]> = Vec::with_capacity(n_vertices);
+        let mut uvs: Vec<[f32; 2]> = Vec::with_capacity(n_vertices);
+
+        positions.push([0.0, 0.0, 0.0]);
+        normals.push(Vec3::X.into());
+        uvs.push([0.5, 0.5]);
+
+        let stride = (sector.end_angle - sector.start_angle) / sector.subdivisions as f32;
+        for side in 0..=sector.subdivisions {
+            let phi = sector.start_angle + stride * side as f32;
+            let (sin, cos) = phi.sin_cos();
+
+            positions.push([0.0, cos * sector.radius, sin * sector.radius]);
+            normals.push(Vec3::X.into());
+            uvs.push([0.5 + cos / 2.0, 0.5 + sin / 2.0]);
+        }
+
+        let mut indices: Vec<u32> = Vec::with_capacity(sector.subdivisions * 3);
+        for point in 1..sector.subdivisions + 1 {
+            indices.push(0);
+            indices.push(point as u32);
+            indices.push(point as u32 + 1);
+        }
+
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.set_indices(Some(Indices::U32(indices)));
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        mesh
+    }
+}
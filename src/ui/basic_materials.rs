@@ -1,14 +1,65 @@
-use bevy::prelude::*;
+use bevy::{
+    pbr::{ExtendedMaterial, MaterialExtension},
+    prelude::*,
+    render::render_resource::{AsBindGroup, ShaderRef},
+};
+
+use crate::ai::terrain::TERRAIN_LAYERS;
 
 pub struct BasicMaterialsPlugin;
 
 impl Plugin for BasicMaterialsPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<BasicMaterials>()
+        app.add_plugins(MaterialPlugin::<TerrainMaterial>::default())
+            .init_resource::<BasicMaterials>()
             .add_systems(Update, flip_materials);
     }
 }
 
+/// `StandardMaterial` blending the `TERRAIN_LAYERS` by the weights in the vertex colors.
+pub type TerrainMaterial = ExtendedMaterial<StandardMaterial, TerrainExtension>;
+
+/// Layer colors & the slope/altitude rule.
+///
+/// With `auto_texture` on, the first layer is replaced by grass on flats,
+/// rock on slopes steeper than `rock_slope` & sand below `sand_height`.
+#[derive(Asset, AsBindGroup, Reflect, Debug, Clone)]
+pub struct TerrainExtension {
+    /// Linear RGBA.
+    #[uniform(100)]
+    pub colors: [Vec4; 4],
+    /// One minus the normal's Y.
+    #[uniform(100)]
+    pub rock_slope: f32,
+    #[uniform(100)]
+    pub slope_blend: f32,
+    #[uniform(100)]
+    pub sand_height: f32,
+    #[uniform(100)]
+    pub height_blend: f32,
+    #[uniform(100)]
+    pub auto_texture: u32,
+}
+
+impl Default for TerrainExtension {
+    fn default() -> Self {
+        Self {
+            colors: TERRAIN_LAYERS.map(|(_, color)| Vec4::from(color.as_linear_rgba_f32())),
+            rock_slope: 0.25,
+            slope_blend: 0.05,
+            sand_height: 0.8,
+            height_blend: 0.3,
+            auto_texture: 1,
+        }
+    }
+}
+
+impl MaterialExtension for TerrainExtension {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/terrain.wgsl".into()
+    }
+}
+
 #[derive(Resource, Reflect)]
 pub struct BasicMaterials {
     pub ui_default: Handle<StandardMaterial>,
@@ -17,13 +68,29 @@ pub struct BasicMaterials {
     pub ui_blue: Handle<StandardMaterial>,
     pub ui_selected: Handle<StandardMaterial>,
     pub ui_transparent: Handle<StandardMaterial>,
-    pub terrain: Handle<StandardMaterial>,
+    pub ui_limits: Handle<StandardMaterial>,
+    pub terrain: Handle<TerrainMaterial>,
     pub salmon: Handle<StandardMaterial>,
     pub gold: Handle<StandardMaterial>,
+    pub building_foundation: Handle<StandardMaterial>,
+    pub building_floor: Handle<StandardMaterial>,
+    pub building_wall: Handle<StandardMaterial>,
+    pub building_roof: Handle<StandardMaterial>,
 }
 
 impl FromWorld for BasicMaterials {
     fn from_world(world: &mut World) -> Self {
+        let terrain = world
+            .resource_mut::<Assets<TerrainMaterial>>()
+            .add(TerrainMaterial {
+                base: StandardMaterial {
+                    metallic: 0.0,
+                    perceptual_roughness: 0.8,
+                    reflectance: 0.2,
+                    ..default()
+                },
+                extension: TerrainExtension::default(),
+            });
         let mut materials = world
             .get_resource_mut::<Assets<StandardMaterial>>()
             .unwrap();
@@ -83,13 +150,17 @@ impl FromWorld for BasicMaterials {
                 alpha_mode: AlphaMode::Add,
                 ..default()
             }),
-            terrain: materials.add(StandardMaterial {
-                base_color: Color::SILVER,
+            ui_limits: materials.add(StandardMaterial {
+                base_color: Color::rgba(0.9, 0.9, 0.5, 0.3),
+                emissive: Color::rgb(0.3, 0.3, 0.1),
                 metallic: 0.0,
                 perceptual_roughness: 0.8,
-                reflectance: 0.2,
+                double_sided: true,
+                cull_mode: None,
+                alpha_mode: AlphaMode::Blend,
                 ..default()
             }),
+            terrain,
             salmon: materials.add(StandardMaterial {
                 base_color: Color::SALMON,
                 metallic: 0.0,
@@ -102,6 +173,30 @@ impl FromWorld for BasicMaterials {
                 perceptual_roughness: 0.5,
                 ..default()
             }),
+            building_foundation: materials.add(StandardMaterial {
+                base_color: Color::rgb(0.35, 0.35, 0.35),
+                metallic: 0.0,
+                perceptual_roughness: 0.9,
+                ..default()
+            }),
+            building_floor: materials.add(StandardMaterial {
+                base_color: Color::rgb(0.6, 0.5, 0.4),
+                metallic: 0.0,
+                perceptual_roughness: 0.7,
+                ..default()
+            }),
+            building_wall: materials.add(StandardMaterial {
+                base_color: Color::rgb(0.85, 0.82, 0.75),
+                metallic: 0.0,
+                perceptual_roughness: 0.8,
+                ..default()
+            }),
+            building_roof: materials.add(StandardMaterial {
+                base_color: Color::rgb(0.55, 0.25, 0.2),
+                metallic: 0.0,
+                perceptual_roughness: 0.8,
+                ..default()
+            }),
         }
     }
 }
@@ -1,13 +1,15 @@
 use std::f32::consts::PI;
 
-use bevy::{prelude::*, window::PrimaryWindow};
+use bevy::{ecs::system::EntityCommands, prelude::*, window::PrimaryWindow};
 use bevy_inspector_egui::egui;
 use bevy_xpbd_3d::prelude::*;
 
 use crate::{
     anim::{
-        joint::{RevoluteJointCommand, SphericalJointCommand},
+        joint::{MotionProfile, RevoluteJointCommand, SphericalJointCommand},
         rig::{KiRevoluteJoint, KiSphericalJoint},
+        rig_def::ExportRig,
+        sequence::{JointStep, JointTarget, QueueJointStep, RecordRigKeyframe, RigTimeline},
     },
     camera::{MainCamera, ScreenPosition},
 };
@@ -308,11 +310,39 @@ pub struct SelectionUiState {
     pub show_inspector: bool,
     pub show_names: bool,
     pub show_move_gizmo: bool,
+    /// Draws the reachable points of selected `KiEffector`s.
+    pub show_effector_reach: bool,
+    /// Resizing a `Floor` also resizes the floors above it.
+    pub resize_floors_above: bool,
     pub revolute_target_angle: i16,
     pub spherical_target_angle_x: i16,
     pub spherical_target_angle_y: i16,
     pub spherical_target_angle_z: i16,
     pub joint_stop_at_collisions: bool,
+    /// Degrees per second.
+    pub joint_speed: i16,
+    /// 0: constant, 1: trapezoidal, 2: S-curve.
+    pub joint_profile: u8,
+    /// Degrees per second squared.
+    pub joint_acceleration: i16,
+    /// Seconds to reach a recorded rig keyframe.
+    pub keyframe_duration: f32,
+    /// Seconds to hold a recorded rig keyframe.
+    pub keyframe_hold: f32,
+}
+
+impl SelectionUiState {
+    fn joint_motion_profile(&self) -> MotionProfile {
+        let acceleration = self.joint_acceleration as f32 * PI / 180.;
+        match self.joint_profile {
+            1 => MotionProfile::Trapezoidal {
+                acceleration,
+                deceleration: acceleration,
+            },
+            2 => MotionProfile::SCurve { acceleration },
+            _ => MotionProfile::Constant,
+        }
+    }
 }
 
 impl Default for SelectionUiState {
@@ -321,11 +351,18 @@ impl Default for SelectionUiState {
             show_inspector: true,
             show_names: true,
             show_move_gizmo: true,
+            show_effector_reach: false,
+            resize_floors_above: true,
             revolute_target_angle: 0,
             spherical_target_angle_x: 0,
             spherical_target_angle_y: 0,
             spherical_target_angle_z: 0,
             joint_stop_at_collisions: false,
+            joint_speed: 45,
+            joint_profile: 0,
+            joint_acceleration: 90,
+            keyframe_duration: 1.,
+            keyframe_hold: 0.5,
         }
     }
 }
@@ -411,6 +448,8 @@ pub fn selection_ui(
         Option<&Name>,
         Option<&KiRevoluteJoint>,
         Option<&KiSphericalJoint>,
+        bool,
+        Option<&RigTimeline>,
     )>,
     mut cmd: Commands,
 ) {
@@ -420,6 +459,8 @@ pub fn selection_ui(
             ui.checkbox(&mut selection.show_names, "Show names");
             ui.checkbox(&mut selection.show_inspector, "Show inspector");
             ui.checkbox(&mut selection.show_move_gizmo, "Show move gizmo");
+            ui.checkbox(&mut selection.show_effector_reach, "Show effector reach");
+            ui.checkbox(&mut selection.resize_floors_above, "Resize floors above");
 
             if !selected.is_empty() {
                 ui.add_space(10.);
@@ -427,7 +468,7 @@ pub fn selection_ui(
                     egui::Color32::DARK_GREEN,
                     format!("{} objects selected:", selected.len()),
                 );
-                for (ent, name, _, _) in selected.iter().take(20) {
+                for (ent, name, ..) in selected.iter().take(20) {
                     if let Some(name) = name {
                         ui.label(format!("- {}", name.as_str()));
                     } else {
@@ -441,26 +482,35 @@ pub fn selection_ui(
 
             if selected.len() == 1 {
                 let single = selected.first().unwrap();
-                if let (ent, _, Some(_), None) = single {
+                if let (ent, _, Some(_), None, ..) = single {
                     ui.group(|ui| {
                         ui.strong("Revolute joint");
                         ui.add(
                             egui::Slider::new(&mut selection.revolute_target_angle, -180..=180)
                                 .text("angle"),
                         );
-                        ui.checkbox(
-                            &mut selection.joint_stop_at_collisions,
-                            "Stop at collisions",
-                        );
-                        if ui.button("Add joint target").clicked() {
-                            cmd.entity(*ent).insert(RevoluteJointCommand::new(
-                                selection.revolute_target_angle as f32 * PI / 180.,
-                                0.01,
-                                selection.joint_stop_at_collisions,
-                            ));
-                        }
+                        joint_motion_ui(ui, selection);
+                        let target = selection.revolute_target_angle as f32 * PI / 180.;
+                        ui.horizontal(|ui| {
+                            if ui.button("Add joint target").clicked() {
+                                cmd.entity(*ent).insert(
+                                    RevoluteJointCommand::new(
+                                        target,
+                                        selection.joint_speed as f32 * PI / 180.,
+                                        selection.joint_stop_at_collisions,
+                                    )
+                                    .with_profile(selection.joint_motion_profile()),
+                                );
+                            }
+                            joint_queue_ui(
+                                ui,
+                                selection,
+                                JointTarget::Revolute(target),
+                                &mut cmd.entity(*ent),
+                            );
+                        });
                     });
-                } else if let (ent, _, None, Some(_)) = single {
+                } else if let (ent, _, None, Some(_), ..) = single {
                     ui.group(|ui| {
                         ui.strong("Spherical joint");
                         ui.add(
@@ -475,25 +525,140 @@ pub fn selection_ui(
                             egui::Slider::new(&mut selection.spherical_target_angle_y, -180..=180)
                                 .text("angle y"),
                         );
-                        ui.checkbox(
-                            &mut selection.joint_stop_at_collisions,
-                            "Stop at collisions",
+                        joint_motion_ui(ui, selection);
+                        let target = Quat::from_euler(
+                            EulerRot::XZY,
+                            selection.spherical_target_angle_x as f32 * PI / 180.,
+                            selection.spherical_target_angle_z as f32 * PI / 180.,
+                            selection.spherical_target_angle_y as f32 * PI / 180.,
                         );
-                        if ui.button("Add joint target").clicked() {
-                            cmd.entity(*ent).insert(SphericalJointCommand::new_euler(
-                                selection.spherical_target_angle_x as f32 * PI / 180.,
-                                selection.spherical_target_angle_z as f32 * PI / 180.,
-                                selection.spherical_target_angle_y as f32 * PI / 180.,
-                                0.02,
-                                selection.joint_stop_at_collisions,
-                            ));
+                        ui.horizontal(|ui| {
+                            if ui.button("Add joint target").clicked() {
+                                cmd.entity(*ent).insert(
+                                    SphericalJointCommand::new(
+                                        target,
+                                        selection.joint_speed as f32 * PI / 180.,
+                                        selection.joint_stop_at_collisions,
+                                    )
+                                    .with_profile(selection.joint_motion_profile()),
+                                );
+                            }
+                            joint_queue_ui(
+                                ui,
+                                selection,
+                                JointTarget::Spherical(target),
+                                &mut cmd.entity(*ent),
+                            );
+                        });
+                    });
+                }
+                if let (ent, name, _, _, true, timeline) = single {
+                    ui.group(|ui| {
+                        ui.strong("Rig");
+                        if ui.button("Export rig").clicked() {
+                            let name = name.map_or(format!("rig_{}", ent.index()), |n| {
+                                n.as_str().to_lowercase().replace(' ', "_")
+                            });
+                            cmd.entity(*ent).add(ExportRig {
+                                path: format!("assets/rigs/export/{name}.rig.ron").into(),
+                            });
                         }
+                        rig_timeline_ui(ui, selection, *ent, *timeline, &mut cmd);
                     });
                 }
             }
         });
 }
 
+fn joint_queue_ui(
+    ui: &mut egui::Ui,
+    selection: &SelectionUiState,
+    target: JointTarget,
+    cmd: &mut EntityCommands,
+) {
+    if ui.button("Queue joint target").clicked() {
+        cmd.add(QueueJointStep(JointStep::Move {
+            target,
+            speed: selection.joint_speed as f32 * PI / 180.,
+            profile: selection.joint_motion_profile(),
+            stop_at_collisions: selection.joint_stop_at_collisions,
+        }));
+    }
+    if ui.button("Queue wait").clicked() {
+        cmd.add(QueueJointStep(JointStep::Wait(1.)));
+    }
+}
+
+fn rig_timeline_ui(
+    ui: &mut egui::Ui,
+    selection: &mut SelectionUiState,
+    root: Entity,
+    timeline: Option<&RigTimeline>,
+    cmd: &mut Commands,
+) {
+    ui.add(egui::Slider::new(&mut selection.keyframe_duration, 0.1..=10.).text("duration (s)"));
+    ui.add(egui::Slider::new(&mut selection.keyframe_hold, 0.0..=10.).text("hold (s)"));
+    if ui.button("Record keyframe").clicked() {
+        cmd.entity(root).add(RecordRigKeyframe {
+            duration: selection.keyframe_duration,
+            hold: selection.keyframe_hold,
+            profile: selection.joint_motion_profile(),
+        });
+    }
+    let Some(timeline) = timeline else {
+        return;
+    };
+    ui.label(format!(
+        "keyframe {}/{}",
+        timeline.current() + 1,
+        timeline.keyframes.len()
+    ));
+    let mut looping = timeline.looping;
+    if ui.checkbox(&mut looping, "Loop").changed() {
+        cmd.entity(root).add(move |mut entity: EntityWorldMut| {
+            if let Some(mut timeline) = entity.get_mut::<RigTimeline>() {
+                timeline.looping = looping;
+            }
+        });
+    }
+    ui.horizontal(|ui| {
+        if timeline.playing {
+            if ui.button("Stop").clicked() {
+                cmd.entity(root).add(|mut entity: EntityWorldMut| {
+                    if let Some(mut timeline) = entity.get_mut::<RigTimeline>() {
+                        timeline.stop();
+                    }
+                });
+            }
+        } else if ui.button("Play").clicked() {
+            cmd.entity(root).add(|mut entity: EntityWorldMut| {
+                if let Some(mut timeline) = entity.get_mut::<RigTimeline>() {
+                    timeline.play();
+                }
+            });
+        }
+        if ui.button("Clear").clicked() {
+            cmd.entity(root).remove::<RigTimeline>();
+        }
+    });
+}
+
+fn joint_motion_ui(ui: &mut egui::Ui, selection: &mut SelectionUiState) {
+    ui.add(egui::Slider::new(&mut selection.joint_speed, 1..=360).text("speed (°/s)"));
+    ui.horizontal(|ui| {
+        ui.radio_value(&mut selection.joint_profile, 0, "constant");
+        ui.radio_value(&mut selection.joint_profile, 1, "trapezoid");
+        ui.radio_value(&mut selection.joint_profile, 2, "S-curve");
+    });
+    if selection.joint_profile != 0 {
+        ui.add(egui::Slider::new(&mut selection.joint_acceleration, 1..=720).text("accel (°/s²)"));
+    }
+    ui.checkbox(
+        &mut selection.joint_stop_at_collisions,
+        "Stop at collisions",
+    );
+}
+
 fn update_selected_move_gizmos(
     selection: Res<SelectionUiState>,
     mut ev_deselected: EventReader<DeselectedEvent>,
@@ -7,8 +7,11 @@ use bevy_egui::{egui, EguiContext, EguiContexts, EguiSettings};
 use bevy_xpbd_3d::prelude::PhysicsDebugConfig;
 
 use crate::{
-    ai::swarm::InitSwarmEvent,
-    anim::rig::{KiRevoluteJoint, KiSphericalJoint},
+    ai::{swarm::InitSwarmEvent, terrain::TERRAIN_LAYERS},
+    anim::{
+        rig::{KiRevoluteJoint, KiRoot, KiSphericalJoint},
+        sequence::RigTimeline,
+    },
 };
 
 use super::selection::{selection_ui, Selected, SelectionUiState};
@@ -40,6 +43,18 @@ pub enum UiMode {
     AddCube,
     ShootBalls,
     AddFox,
+    AddRig,
+    AddBuilding,
+    AddDoor,
+    AddStairs,
+    AddRamp,
+    AnchorWall,
+    SculptRaise,
+    SculptLower,
+    SculptSmooth,
+    SculptFlatten,
+    PaintTerrain,
+    Ruler,
 }
 
 #[derive(Resource, Reflect)]
@@ -51,8 +66,25 @@ pub struct SidePanel {
     pub show_world: bool,
     pub mode: UiMode,
     pub physics_debug_enabled: bool,
+    /// Draws the nav meshes of all rooms.
+    pub nav_mesh_debug_enabled: bool,
     pub panel_width: f32,
     pub inspector_width: f32,
+    /// Number of floors of buildings placed in `UiMode::AddBuilding`.
+    pub building_floors: u8,
+    /// Size of doors placed in `UiMode::AddDoor`.
+    pub door_width: f32,
+    pub door_height: f32,
+    /// Brush of the terrain sculpting & painting modes.
+    pub brush_radius: f32,
+    pub brush_strength: f32,
+    /// Index in `TERRAIN_LAYERS` painted in `UiMode::PaintTerrain`.
+    pub paint_layer: usize,
+    /// Picks the first terrain layer by slope & altitude.
+    pub terrain_auto_texture: bool,
+    /// Ground points measured in `UiMode::Ruler`.
+    pub ruler_start: Option<Vec3>,
+    pub ruler_end: Option<Vec3>,
 }
 
 impl Default for SidePanel {
@@ -64,8 +96,18 @@ impl Default for SidePanel {
             show_world: true,
             mode: UiMode::Select,
             physics_debug_enabled: false,
+            nav_mesh_debug_enabled: false,
             panel_width: 0.0,
             inspector_width: 0.0,
+            building_floors: 3,
+            door_width: 1.,
+            door_height: 2.2,
+            brush_radius: 5.,
+            brush_strength: 2.,
+            paint_layer: 1,
+            terrain_auto_texture: true,
+            ruler_start: None,
+            ruler_end: None,
         }
     }
 }
@@ -84,6 +126,8 @@ fn main_panel(
             Option<&Name>,
             Option<&KiRevoluteJoint>,
             Option<&KiSphericalJoint>,
+            Has<KiRoot>,
+            Option<&RigTimeline>,
         ),
         With<Selected>,
     >,
@@ -129,6 +173,7 @@ fn main_panel(
                 .show(ui, |ui| {
                     ui.checkbox(&mut panel.physics_debug_enabled, "Debug render");
                     physics_debug_config.enabled = panel.physics_debug_enabled;
+                    ui.checkbox(&mut panel.nav_mesh_debug_enabled, "Nav meshes");
 
                     if ui.button("Toggle swarm").clicked() {
                         ev_init_swarm.send(InitSwarmEvent);
@@ -140,6 +185,58 @@ fn main_panel(
                 .show(ui, |ui| {
                     ui_mode_toggle(ui, &mut panel, UiMode::ShootBalls, "Shoot balls");
                     ui_mode_toggle(ui, &mut panel, UiMode::AddFox, "Add fox");
+                    ui_mode_toggle(ui, &mut panel, UiMode::AddRig, "Add rig");
+                    ui_mode_toggle(ui, &mut panel, UiMode::AddBuilding, "Add building");
+                    if panel.mode == UiMode::AddBuilding {
+                        ui.add(
+                            egui::Slider::new(&mut panel.building_floors, 1..=10).text("floors"),
+                        );
+                    }
+                    ui_mode_toggle(ui, &mut panel, UiMode::AddDoor, "Add door");
+                    if panel.mode == UiMode::AddDoor {
+                        ui.add(egui::Slider::new(&mut panel.door_width, 0.5..=3.).text("width"));
+                        ui.add(egui::Slider::new(&mut panel.door_height, 1.5..=3.).text("height"));
+                    }
+                    ui_mode_toggle(ui, &mut panel, UiMode::AddStairs, "Add stairs");
+                    ui_mode_toggle(ui, &mut panel, UiMode::AddRamp, "Add ramp");
+                    ui_mode_toggle(ui, &mut panel, UiMode::AnchorWall, "Anchor wall");
+                });
+
+            egui::CollapsingHeader::new("Terrain")
+                .default_open(true)
+                .show(ui, |ui| {
+                    ui.checkbox(&mut panel.terrain_auto_texture, "Auto texture");
+                    ui_mode_toggle(ui, &mut panel, UiMode::SculptRaise, "Raise");
+                    ui_mode_toggle(ui, &mut panel, UiMode::SculptLower, "Lower");
+                    ui_mode_toggle(ui, &mut panel, UiMode::SculptSmooth, "Smooth");
+                    ui_mode_toggle(ui, &mut panel, UiMode::SculptFlatten, "Flatten");
+                    ui_mode_toggle(ui, &mut panel, UiMode::PaintTerrain, "Paint");
+                    if panel.mode == UiMode::PaintTerrain {
+                        let layer = TERRAIN_LAYERS[panel.paint_layer].0;
+                        ui.add(
+                            egui::Slider::new(&mut panel.paint_layer, 0..=TERRAIN_LAYERS.len() - 1)
+                                .text(layer),
+                        );
+                    }
+                    ui.add(egui::Slider::new(&mut panel.brush_radius, 1.0..=50.).text("radius"));
+                    ui.add(
+                        egui::Slider::new(&mut panel.brush_strength, 0.1..=10.).text("strength"),
+                    );
+                    ui_mode_toggle(ui, &mut panel, UiMode::Ruler, "Ruler");
+                    if panel.mode == UiMode::Ruler {
+                        if let (Some(start), Some(end)) = (panel.ruler_start, panel.ruler_end) {
+                            let delta = end - start;
+                            let horizontal = delta.xz().length();
+                            ui.label(format!("distance: {:.2} m", delta.length()));
+                            ui.label(format!("horizontal: {horizontal:.2} m"));
+                            ui.label(format!("height: {:+.2} m", delta.y));
+                            if horizontal > 0. {
+                                ui.label(format!("slope: {:.1}%", 100. * delta.y / horizontal));
+                            }
+                        } else {
+                            ui.label("Click two ground points");
+                        }
+                    }
                 });
         })
         .response
@@ -287,7 +287,7 @@ impl EntityCommand for RemoveTransformGizmo {
 pub struct HasTransformGizmo;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
-enum GizmoAxis {
+pub(crate) enum GizmoAxis {
     X,
     Y,
     Z,
@@ -320,7 +320,7 @@ impl GizmoAxis {
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
-enum GizmoPlane {
+pub(crate) enum GizmoPlane {
     XY,
     YZ,
     ZX,
@@ -348,7 +348,7 @@ impl GizmoPlane {
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
-enum GizmoConstraint {
+pub(crate) enum GizmoConstraint {
     Axis(GizmoAxis),
     Plane {
         plane: GizmoPlane,
@@ -357,7 +357,7 @@ enum GizmoConstraint {
 }
 
 impl GizmoConstraint {
-    fn ray_cast(&self, origin: Vec3, ray: &Ray, gtr: &GlobalTransform) -> Option<Vec3> {
+    pub(crate) fn ray_cast(&self, origin: Vec3, ray: &Ray, gtr: &GlobalTransform) -> Option<Vec3> {
         let ray_p = parry3d::query::Ray::new(ray.origin.into(), ray.direction.into());
         match self {
             GizmoConstraint::Axis(axis) => {
@@ -424,7 +424,7 @@ struct TransformGizmoPart {
 }
 
 #[derive(Resource, Reflect)]
-struct TransformGizmoMeshes {
+pub(crate) struct TransformGizmoMeshes {
     pub bar: Handle<Mesh>,
     pub cone: Handle<Mesh>,
     pub ball: Handle<Mesh>,
@@ -432,10 +432,10 @@ struct TransformGizmoMeshes {
     pub cylinder: Handle<Mesh>,
 }
 
-const BAR_H: f32 = 1.0;
-const BAR_W: f32 = 0.025;
-const CONE_R: f32 = 0.1;
-const CONE_H: f32 = 0.25;
+pub(crate) const BAR_H: f32 = 1.0;
+pub(crate) const BAR_W: f32 = 0.025;
+pub(crate) const CONE_R: f32 = 0.1;
+pub(crate) const CONE_H: f32 = 0.25;
 const BALL_R: f32 = 0.1;
 const SQUARE_H: f32 = 0.25;
 const CYLINDER_R: f32 = 0.1;